@@ -87,6 +87,10 @@ impl<T: ComInterface + ?Sized> ComPtr<T> {
     pub unsafe fn cast<U: ComInterface + ?Sized>(self) -> ComPtr<U> {
         mem::transmute(self)
     }
+
+    pub fn as_ptr(&self) -> *mut *mut T::VTable {
+        self.inner.as_ptr()
+    }
 }
 
 impl<T: ComInterface + ?Sized> fmt::Debug for ComPtr<T> {
@@ -744,6 +748,14 @@ pub enum CorePath {
     SkinsCommon = 11,
 }
 
+com_trait! {
+    pub trait IAIMPServiceConfig: IAIMPConfig {
+        const IID = {0x41494D50, 0x5372, 0x7643, 0x66, 0x67, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00};
+
+        unsafe fn flush_cache(&self,) -> HRESULT;
+    }
+}
+
 com_trait! {
     pub trait IAIMPPlugin: IUnknown {
         const IID = {0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0};
@@ -2171,6 +2183,20 @@ com_trait! {
     }
 }
 
+com_trait! {
+    pub trait IAIMPExtensionAudioDecoderOld: IUnknown {
+        const IID = {0x41494D50, 0x4578, 0x7441, 0x75, 0x64, 0x69, 0x6F, 0x44, 0x65, 0x63, 0x4F};
+
+        unsafe fn create_decoder(
+            &self,
+            file_name: ComRc<dyn IAIMPString>,
+            flags: DecoderFlags,
+            error_info: ComPtr<dyn IAIMPErrorInfo>,
+            decoder: *mut ComRc<dyn IAIMPAudioDecoder>,
+        ) -> WinHRESULT;
+    }
+}
+
 bitflags! {
     pub struct DecoderFlags: DWORD {
         const NONE = 0;
@@ -2235,6 +2261,7 @@ com_trait! {
 }
 
 #[repr(transparent)]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
 pub struct BufferingProgress(c_double);
 
 impl BufferingProgress {
@@ -2245,6 +2272,10 @@ impl BufferingProgress {
             None
         }
     }
+
+    pub fn get(self) -> c_double {
+        self.0
+    }
 }
 
 com_trait! {
@@ -2272,6 +2303,33 @@ bitflags! {
     }
 }
 
+// Messages
+
+pub const AIMP_MSG_EVENT_BASE: DWORD = 0x2000;
+pub const AIMP_MSG_EVENT_OPTIONS: DWORD = AIMP_MSG_EVENT_BASE + 2;
+
+com_trait! {
+    pub trait IAIMPMessageHook: IUnknown {
+        const IID = {0xFC6FB524, 0xA959, 0x4089, 0xAA, 0x0A, 0xEA, 0x40, 0xAB, 0x73, 0x74, 0xCD};
+
+        unsafe fn core_message(&self, message: DWORD, param1: c_int, param2: *mut c_void, result: *mut WinHRESULT,) -> ();
+    }
+}
+
+com_trait! {
+    pub trait IAIMPServiceMessageDispatcher: IUnknown {
+        const IID = {0x41494D50, 0x5372, 0x764D, 0x73, 0x67, 0x44, 0x73, 0x70, 0x72, 0x00, 0x00};
+
+        unsafe fn send(&self, message: DWORD, param1: c_int, param2: *mut c_void,) -> HRESULT;
+
+        unsafe fn register(&self, message_name: PWCHAR,) -> DWORD;
+
+        unsafe fn hook(&self, hook: ComPtr<dyn IAIMPMessageHook>,) -> HRESULT;
+
+        unsafe fn unhook(&self, hook: ComPtr<dyn IAIMPMessageHook>,) -> HRESULT;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::mem::MaybeUninit;
@@ -0,0 +1,85 @@
+use crate::{error::HresultExt, util::Service, AimpString, Result};
+use iaimp::{ComPtr, IAIMPConfig, IAIMPServiceConfig};
+use std::mem::MaybeUninit;
+use winapi::shared::winerror::S_OK;
+
+pub static CONFIG: Service<Config> = Service::new();
+
+pub struct Config(ComPtr<dyn IAIMPServiceConfig>);
+
+impl Config {
+    pub fn get<T: ConfigValue, U: Into<AimpString>>(&self, key_path: U) -> Option<T> {
+        T::get(key_path.into(), self)
+    }
+
+    pub fn set<T: ConfigValue, U: Into<AimpString>>(&self, key_path: U, value: T) -> Result<()> {
+        value.set(key_path.into(), self)
+    }
+
+    pub fn delete<T: Into<AimpString>>(&self, key_path: T) -> Result<()> {
+        unsafe { self.0.delete(key_path.into().0).into_result() }
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        unsafe { self.0.flush_cache().into_result() }
+    }
+}
+
+impl From<ComPtr<dyn IAIMPServiceConfig>> for Config {
+    fn from(ptr: ComPtr<dyn IAIMPServiceConfig>) -> Self {
+        Self(ptr)
+    }
+}
+
+pub trait ConfigValue: Sized {
+    fn get(key_path: AimpString, config: &Config) -> Option<Self>;
+
+    fn set(self, key_path: AimpString, config: &Config) -> Result<()>;
+}
+
+macro_rules! impl_config_value {
+    ($ty:ty, $get:ident, $set:ident) => {
+        impl ConfigValue for $ty {
+            fn get(key_path: AimpString, config: &Config) -> Option<Self> {
+                unsafe {
+                    let mut value = MaybeUninit::uninit();
+                    if (config.0).$get(key_path.0, value.as_mut_ptr()) == S_OK {
+                        Some(value.assume_init())
+                    } else {
+                        None
+                    }
+                }
+            }
+
+            fn set(self, key_path: AimpString, config: &Config) -> Result<()> {
+                unsafe { (config.0).$set(key_path.0, self).into_result() }
+            }
+        }
+    };
+}
+
+impl_config_value!(f64, get_value_as_float, set_value_as_float);
+impl_config_value!(i32, get_value_as_int32, set_value_as_int32);
+impl_config_value!(i64, get_value_as_int64, set_value_as_int64);
+
+impl ConfigValue for AimpString {
+    fn get(key_path: AimpString, config: &Config) -> Option<Self> {
+        unsafe {
+            let mut value = MaybeUninit::uninit();
+            if config.0.get_value_as_string(key_path.0, value.as_mut_ptr()) == S_OK {
+                Some(AimpString(value.assume_init()))
+            } else {
+                None
+            }
+        }
+    }
+
+    fn set(self, key_path: AimpString, config: &Config) -> Result<()> {
+        unsafe {
+            config
+                .0
+                .set_value_as_string(key_path.0, self.0)
+                .into_result()
+        }
+    }
+}
@@ -1,4 +1,30 @@
+pub use adapter::{ChannelMap, ConvertingDecoder};
+pub use conformance::{ConformanceFailure, ConformanceReport, DecoderConformance};
+pub use export::{export, export_decoder, ExportError, ExportOptions, ExportTask};
+#[cfg(feature = "decoders-pcm")]
+pub use formats::{
+    AiffBuilder, AiffFileFormat, PcmDecoder, PcmError, RawPcmBuilder, WavBuilder, WavFileFormat,
+};
+pub use gapless::Gapless;
 pub use iaimp::{BufferingProgress, DecoderChange, SampleFormat};
+pub use pcm::{
+    convert, convert_with_dither, decode, encode, sample_size, Dither, Frames, PcmReader,
+    PcmWriter, Sample, SampleFormatError, I24,
+};
+pub use radio::RadioDecoder;
+pub use router::{DecoderRouter, Probe};
+pub use tone::{Tone, ToneDecoder, ToneError, ToneGenerator, Waveform};
+
+mod adapter;
+mod conformance;
+mod export;
+#[cfg(feature = "decoders-pcm")]
+mod formats;
+mod gapless;
+mod pcm;
+mod radio;
+mod router;
+mod tone;
 
 use crate::{
     core::Extension,
@@ -8,13 +34,16 @@ use crate::{
     util::Service,
     AimpString, ErrorInfo, ErrorInfoContent, Result,
 };
+use gapless::Trimmed;
 use iaimp::{
     com_wrapper, ComInterface, ComInterfaceQuerier, ComPtr, ComRc, DecoderFlags, IAIMPAudioDecoder,
     IAIMPAudioDecoderBufferingProgress, IAIMPAudioDecoderListener, IAIMPAudioDecoderNotifications,
-    IAIMPErrorInfo, IAIMPExtensionAudioDecoder, IAIMPExtensionAudioDecoderPriority, IAIMPFileInfo,
-    IAIMPServiceAudioDecoders, IAIMPStream, IUnknown, IID,
+    IAIMPErrorInfo, IAIMPExtensionAudioDecoder, IAIMPExtensionAudioDecoderOld,
+    IAIMPExtensionAudioDecoderPriority, IAIMPFileInfo, IAIMPServiceAudioDecoders, IAIMPStream,
+    IAIMPString, IUnknown, IID,
 };
-use std::{cell::Cell, io, mem, mem::MaybeUninit, os::raw::c_void, slice};
+use parking_lot::{Mutex, RwLock};
+use std::{cell::Cell, io, mem, mem::MaybeUninit, os::raw::c_void, slice, sync::Arc};
 use winapi::shared::{
     minwindef::{BOOL, FALSE, TRUE},
     winerror::{E_FAIL, E_PENDING, HRESULT, S_OK},
@@ -27,35 +56,84 @@ pub trait AudioDecoderBuilder {
     const ONLY_INSTANCE: bool;
 
     type Decoder: AudioDecoder;
-    type Error: std::error::Error;
+    type Error: DecoderError;
 
     fn create(&self, stream: Stream) -> Result<Self::Decoder, Self::Error>;
 }
 
+/// Error of decoder creation reported to AIMP
+pub trait DecoderError: std::error::Error {
+    /// Stream is not in format of builder, AIMP silently tries other decoders then
+    ///
+    /// Otherwise stream is considered corrupt and error is shown to user
+    fn is_unsupported(&self) -> bool {
+        false
+    }
+
+    fn code(&self) -> i32 {
+        1
+    }
+
+    fn message(&self) -> String {
+        self.to_string()
+    }
+
+    fn details(&self) -> Option<String> {
+        None
+    }
+}
+
+impl DecoderError for io::Error {
+    fn is_unsupported(&self) -> bool {
+        self.kind() == io::ErrorKind::Unsupported
+    }
+}
+
+impl DecoderError for crate::Error {}
+
+/// Builder of decoders that open files by name themselves
+pub trait AudioDecoderFileBuilder {
+    const PRIORITY: Option<i32>;
+    const ONLY_INSTANCE: bool;
+
+    type Decoder: AudioDecoder;
+    type Error: DecoderError;
+
+    fn create(&self, file_name: AimpString) -> Result<Self::Decoder, Self::Error>;
+}
+
+/// Decoder methods are called by AIMP from playback thread one at a time,
+/// `buffering_progress` and `notifications` can be called from other threads meanwhile
 pub trait AudioDecoder {
-    fn file_info(&self) -> Option<FileInfo>;
+    fn file_info(&mut self) -> Option<FileInfo>;
 
-    fn stream_info(&self) -> Option<StreamInfo>;
+    fn stream_info(&mut self) -> Option<StreamInfo>;
 
-    fn is_seekable(&self) -> bool;
+    fn is_seekable(&mut self) -> bool;
 
-    fn is_realtime_stream(&self) -> bool;
+    fn is_realtime_stream(&mut self) -> bool;
 
-    fn available_data(&self) -> i64 {
+    fn available_data(&mut self) -> i64 {
         self.size() - self.pos()
     }
 
-    fn size(&self) -> i64;
+    fn size(&mut self) -> i64;
 
-    fn pos(&self) -> i64;
+    fn pos(&mut self) -> i64;
 
-    fn set_pos(&self, pos: i64) -> bool;
+    fn set_pos(&mut self, pos: i64) -> bool;
 
-    fn read(&self, buf: &mut [u8]) -> i32;
+    fn read(&mut self, buf: &mut [u8]) -> i32;
 
     fn buffering_progress(&self) -> Option<BufferingProgress>;
 
-    fn notifications<'a>(&self) -> Option<&'a AudioDecoderNotificationsWrapper>;
+    /// Called once when decoder is passed to AIMP
+    fn notifications(&self) -> Option<AudioDecoderNotificationsWrapper>;
+
+    /// Frames to trim, AIMP gets positions and size without them
+    fn gapless(&mut self) -> Gapless {
+        Gapless::default()
+    }
 }
 
 impl io::Read for dyn AudioDecoder {
@@ -64,11 +142,58 @@ impl io::Read for dyn AudioDecoder {
     }
 }
 
-struct AudioDecoderWrapper<T>(T);
+struct AudioDecoderWrapper<T> {
+    inner: RwLock<Trimmed<T>>,
+    // reported while decoder is busy
+    last_progress: Mutex<Option<BufferingProgress>>,
+    // kept outside of the lock so listeners can be changed during `read`
+    notifications: Option<AudioDecoderNotificationsWrapper>,
+}
+
+thread_local! {
+    // decoder methods called by AIMP on current thread, `ListenerSet` delays changes until they return
+    static DECODER_CALLS: Cell<usize> = const { Cell::new(0) };
+}
+
+impl<T: AudioDecoder> AudioDecoderWrapper<T> {
+    fn new(decoder: T) -> Self {
+        Self {
+            notifications: decoder.notifications(),
+            inner: RwLock::new(Trimmed::new(decoder)),
+            last_progress: Mutex::new(None),
+        }
+    }
+
+    // changes are delivered when the lock is released,
+    // so listeners can call the decoder back
+    fn with_decoder<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Trimmed<T>) -> R,
+    {
+        DECODER_CALLS.with(|calls| calls.set(calls.get() + 1));
+        let res = f(&mut self.inner.write());
+        DECODER_CALLS.with(|calls| calls.set(calls.get() - 1));
+        if let Some(notifications) = &self.notifications {
+            notifications.flush();
+        }
+        res
+    }
+
+    fn buffering_progress(&self) -> Option<BufferingProgress> {
+        match self.inner.try_read() {
+            Some(decoder) => {
+                let progress = decoder.buffering_progress();
+                *self.last_progress.lock() = progress;
+                progress
+            }
+            None => *self.last_progress.lock(),
+        }
+    }
+}
 
 impl<T: AudioDecoder> IAIMPAudioDecoder for AudioDecoderWrapper<T> {
     unsafe fn get_file_info(&self, file_info: ComPtr<dyn IAIMPFileInfo>) -> BOOL {
-        if let Some(info) = self.0.file_info() {
+        if let Some(info) = self.with_decoder(|decoder| decoder.file_info()) {
             let mut file_info = FileInfo::from(ComRc::from(file_info));
             file_info.clone_from(&info);
             mem::forget(file_info);
@@ -84,7 +209,7 @@ impl<T: AudioDecoder> IAIMPAudioDecoder for AudioDecoderWrapper<T> {
         channels: *mut i32,
         sample_format: *mut SampleFormat,
     ) -> BOOL {
-        if let Some(info) = self.0.stream_info() {
+        if let Some(info) = self.with_decoder(|decoder| decoder.stream_info()) {
             *sample_rate = info.sample_rate;
             *channels = info.channels;
             *sample_format = info.sample_format;
@@ -95,38 +220,38 @@ impl<T: AudioDecoder> IAIMPAudioDecoder for AudioDecoderWrapper<T> {
     }
 
     unsafe fn is_seekable(&self) -> BOOL {
-        self.0.is_seekable() as BOOL
+        self.with_decoder(|decoder| decoder.is_seekable()) as BOOL
     }
 
     unsafe fn is_realtime_stream(&self) -> BOOL {
-        self.0.is_realtime_stream() as BOOL
+        self.with_decoder(|decoder| decoder.is_realtime_stream()) as BOOL
     }
 
     unsafe fn get_available_data(&self) -> i64 {
-        self.0.available_data()
+        self.with_decoder(|decoder| decoder.available_data())
     }
 
     unsafe fn get_size(&self) -> i64 {
-        self.0.size()
+        self.with_decoder(|decoder| decoder.size())
     }
 
     unsafe fn get_position(&self) -> i64 {
-        self.0.pos()
+        self.with_decoder(|decoder| decoder.pos())
     }
 
     unsafe fn set_position(&self, position: i64) -> i32 {
-        self.0.set_pos(position) as BOOL
+        self.with_decoder(|decoder| decoder.set_pos(position)) as BOOL
     }
 
     unsafe fn read(&self, buffer: *mut c_void, count: i32) -> i32 {
-        self.0
-            .read(slice::from_raw_parts_mut(buffer as *mut _, count as usize))
+        let buf = slice::from_raw_parts_mut(buffer as *mut _, count as usize);
+        self.with_decoder(|decoder| decoder.read(buf))
     }
 }
 
 impl<T: AudioDecoder> IAIMPAudioDecoderBufferingProgress for AudioDecoderWrapper<T> {
     unsafe fn get(&self, value: *mut BufferingProgress) -> i32 {
-        if let Some(progress) = self.0.buffering_progress() {
+        if let Some(progress) = self.buffering_progress() {
             *value = progress;
             TRUE
         } else {
@@ -137,16 +262,14 @@ impl<T: AudioDecoder> IAIMPAudioDecoderBufferingProgress for AudioDecoderWrapper
 
 impl<T: AudioDecoder> IAIMPAudioDecoderNotifications for AudioDecoderWrapper<T> {
     unsafe fn listener_add(&self, listener: ComRc<dyn IAIMPAudioDecoderListener>) {
-        if let Some(notifications) = self.0.notifications() {
-            notifications.0.add_listener(AudioDecoderListener(listener))
+        if let Some(notifications) = &self.notifications {
+            notifications.add_listener(AudioDecoderListener(listener))
         }
     }
 
     unsafe fn listener_remove(&self, listener: ComRc<dyn IAIMPAudioDecoderListener>) {
-        if let Some(notifications) = self.0.notifications() {
-            notifications
-                .0
-                .remove_listener(AudioDecoderListener(listener))
+        if let Some(notifications) = &self.notifications {
+            notifications.remove_listener(AudioDecoderListener(listener))
         }
     }
 }
@@ -154,9 +277,9 @@ impl<T: AudioDecoder> IAIMPAudioDecoderNotifications for AudioDecoderWrapper<T>
 impl<T: AudioDecoder> ComInterfaceQuerier for AudioDecoderWrapper<T> {
     fn query_interface(&self, riid: &IID) -> bool {
         if riid == &<dyn IAIMPAudioDecoderBufferingProgress>::IID {
-            self.0.buffering_progress().is_some()
+            self.buffering_progress().is_some()
         } else if riid == &<dyn IAIMPAudioDecoderNotifications>::IID {
-            self.0.notifications().is_some()
+            self.notifications.is_some()
         } else {
             true
         }
@@ -170,26 +293,171 @@ pub struct StreamInfo {
     pub sample_format: SampleFormat,
 }
 
-pub struct AudioDecoderNotificationsWrapper(Box<dyn AudioDecoderNotifications>);
+pub trait AudioDecoderNotifications {
+    fn add_listener(&self, listener: AudioDecoderListener);
+
+    fn remove_listener(&self, listener: AudioDecoderListener);
+
+    /// Called when decoder method returns to AIMP
+    fn flush(&self) {}
+}
+
+#[derive(Clone)]
+pub struct AudioDecoderNotificationsWrapper(Arc<dyn AudioDecoderNotifications>);
 
 impl AudioDecoderNotificationsWrapper {
     pub fn new<T: AudioDecoderNotifications + 'static>(notifications: T) -> Self {
-        Self(Box::new(notifications))
+        Self(Arc::new(notifications))
     }
 }
 
-pub trait AudioDecoderNotifications {
-    fn add_listener(&self, listener: AudioDecoderListener);
+impl AudioDecoderNotifications for AudioDecoderNotificationsWrapper {
+    fn add_listener(&self, listener: AudioDecoderListener) {
+        self.0.add_listener(listener)
+    }
 
-    fn remove_listener(&self, listener: AudioDecoderListener);
+    fn remove_listener(&self, listener: AudioDecoderListener) {
+        self.0.remove_listener(listener)
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
 }
 
+impl From<ListenerSet> for AudioDecoderNotificationsWrapper {
+    fn from(listeners: ListenerSet) -> Self {
+        Self::new(listeners)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct AudioDecoderListener(ComRc<dyn IAIMPAudioDecoderListener>);
 
 impl AudioDecoderListener {
     pub fn changed(&self, changes: DecoderChange) {
         unsafe { self.0.changed(changes) }
     }
+
+    // pointers of different interfaces of one object are equal only as `IUnknown`
+    fn identity(&self) -> Option<*mut c_void> {
+        unsafe {
+            let mut ppv = MaybeUninit::uninit();
+            if self
+                .0
+                .query_interface(&<dyn IUnknown>::IID, ppv.as_mut_ptr())
+                != S_OK
+            {
+                return None;
+            }
+            let ppv = ppv.assume_init();
+            drop(ComRc::<dyn IUnknown>::from_ptr(ppv as _));
+            Some(ppv)
+        }
+    }
+}
+
+impl From<ComRc<dyn IAIMPAudioDecoderListener>> for AudioDecoderListener {
+    fn from(rc: ComRc<dyn IAIMPAudioDecoderListener>) -> Self {
+        Self(rc)
+    }
+}
+
+impl PartialEq for AudioDecoderListener {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.identity(), other.identity()) {
+            (Some(identity), Some(other_identity)) => identity == other_identity,
+            _ => self.0 == other.0,
+        }
+    }
+}
+
+impl Eq for AudioDecoderListener {}
+
+struct Listeners {
+    listeners: Vec<AudioDecoderListener>,
+    pending: DecoderChange,
+}
+
+impl Default for Listeners {
+    fn default() -> Self {
+        Self {
+            listeners: Vec::new(),
+            pending: DecoderChange::NONE,
+        }
+    }
+}
+
+/// Listeners registered by AIMP, cloned handles share them
+#[derive(Clone, Default)]
+pub struct ListenerSet(Arc<Mutex<Listeners>>);
+
+// AIMP listeners can be called from any thread
+unsafe impl Send for ListenerSet {}
+unsafe impl Sync for ListenerSet {}
+
+impl ListenerSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Listener is added only once
+    pub fn add(&self, listener: AudioDecoderListener) {
+        let mut inner = self.0.lock();
+        if !inner.listeners.contains(&listener) {
+            inner.listeners.push(listener);
+        }
+    }
+
+    pub fn remove(&self, listener: &AudioDecoderListener) {
+        self.0.lock().listeners.retain(|l| l != listener);
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().listeners.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Changes are delivered immediately or, if decoder method is running on this thread,
+    /// when it returns to AIMP, so listeners can call the decoder back
+    pub fn notify(&self, changes: DecoderChange) {
+        self.0.lock().pending |= changes;
+        if DECODER_CALLS.with(Cell::get) == 0 {
+            self.flush();
+        }
+    }
+
+    /// Delivers pending changes, must not be called inside decoder methods
+    pub fn flush(&self) {
+        let (listeners, changes) = {
+            let mut inner = self.0.lock();
+            if inner.pending.is_empty() {
+                return;
+            }
+            let changes = mem::replace(&mut inner.pending, DecoderChange::NONE);
+            (inner.listeners.clone(), changes)
+        };
+        for listener in listeners {
+            listener.changed(changes);
+        }
+    }
+}
+
+impl AudioDecoderNotifications for ListenerSet {
+    fn add_listener(&self, listener: AudioDecoderListener) {
+        self.add(listener)
+    }
+
+    fn remove_listener(&self, listener: AudioDecoderListener) {
+        self.remove(&listener)
+    }
+
+    fn flush(&self) {
+        ListenerSet::flush(self)
+    }
 }
 
 pub struct AudioDecoderBuilderWrapper<T> {
@@ -206,6 +474,92 @@ impl<T> AudioDecoderBuilderWrapper<T> {
     }
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Created {
+    Decoder,
+    Pending,
+    Unsupported,
+    Failed,
+}
+
+impl Created {
+    fn hresult(self) -> HRESULT {
+        match self {
+            Created::Decoder => S_OK,
+            Created::Pending => E_PENDING,
+            Created::Unsupported | Created::Failed => E_FAIL,
+        }
+    }
+}
+
+unsafe fn create_decoder<D, E, F>(
+    once_inited: &Cell<bool>,
+    only_instance: bool,
+    flags: DecoderFlags,
+    error_info: ComPtr<dyn IAIMPErrorInfo>,
+    decoder: *mut ComRc<dyn IAIMPAudioDecoder>,
+    create: F,
+) -> Created
+where
+    D: AudioDecoder,
+    E: DecoderError,
+    F: FnOnce() -> Result<D, E>,
+{
+    if once_inited.get() && !flags.contains(DecoderFlags::FORCE_CREATE_INSTANCE) && only_instance {
+        return Created::Pending;
+    }
+
+    match create() {
+        Ok(tdecoder) => {
+            once_inited.set(true);
+
+            let wrapper = com_wrapper!(
+                AudioDecoderWrapper::new(tdecoder) =>
+                dyn IAIMPAudioDecoder,
+                dyn IAIMPAudioDecoderBufferingProgress,
+                dyn IAIMPAudioDecoderNotifications
+            )
+            .into_com_rc();
+            decoder.write(wrapper);
+
+            Created::Decoder
+        }
+        Err(err) if err.is_unsupported() => Created::Unsupported,
+        Err(err) => {
+            error_info.add_ref();
+            let mut error_info = ErrorInfo(ComRc::from(error_info));
+            error_info.set(&ErrorInfoContent {
+                code: err.code(),
+                msg: AimpString::from(err.message()),
+                details: err.details().map(AimpString::from),
+            });
+            Created::Failed
+        }
+    }
+}
+
+impl<T: AudioDecoderBuilder> AudioDecoderBuilderWrapper<T> {
+    unsafe fn try_create(
+        &self,
+        stream: ComRc<dyn IAIMPStream>,
+        flags: DecoderFlags,
+        error_info: ComPtr<dyn IAIMPErrorInfo>,
+        decoder: *mut ComRc<dyn IAIMPAudioDecoder>,
+    ) -> Created {
+        create_decoder(
+            &self.once_inited,
+            T::ONLY_INSTANCE,
+            flags,
+            error_info,
+            decoder,
+            || {
+                stream.add_ref();
+                self.inner.create(Stream(stream))
+            },
+        )
+    }
+}
+
 impl<T: AudioDecoderBuilder> IAIMPExtensionAudioDecoder for AudioDecoderBuilderWrapper<T> {
     unsafe fn create_decoder(
         &self,
@@ -214,40 +568,8 @@ impl<T: AudioDecoderBuilder> IAIMPExtensionAudioDecoder for AudioDecoderBuilderW
         error_info: ComPtr<dyn IAIMPErrorInfo>,
         decoder: *mut ComRc<dyn IAIMPAudioDecoder>,
     ) -> HRESULT {
-        let once_inited = self.once_inited.get();
-        if once_inited && !flags.contains(DecoderFlags::FORCE_CREATE_INSTANCE) && T::ONLY_INSTANCE {
-            return E_PENDING;
-        }
-
-        stream.add_ref();
-        let stream = Stream(stream);
-        let res = self.inner.create(stream);
-        match res {
-            Ok(tdecoder) => {
-                self.once_inited.set(true);
-
-                let wrapper = com_wrapper!(
-                    AudioDecoderWrapper(tdecoder) =>
-                    dyn IAIMPAudioDecoder,
-                    dyn IAIMPAudioDecoderBufferingProgress,
-                    dyn IAIMPAudioDecoderNotifications
-                )
-                .into_com_rc();
-                decoder.write(wrapper);
-
-                S_OK
-            }
-            Err(err) => {
-                error_info.add_ref();
-                let mut error_info = ErrorInfo(ComRc::from(error_info));
-                error_info.set(&ErrorInfoContent {
-                    code: 1,
-                    msg: AimpString::from(err.to_string()),
-                    details: None,
-                });
-                E_FAIL
-            }
-        }
+        self.try_create(stream, flags, error_info, decoder)
+            .hresult()
     }
 }
 
@@ -284,6 +606,80 @@ impl<T: AudioDecoderBuilder> ComInterfaceQuerier for AudioDecoderBuilderWrapper<
     }
 }
 
+pub struct AudioDecoderFileBuilderWrapper<T> {
+    inner: T,
+    once_inited: Cell<bool>,
+}
+
+impl<T> AudioDecoderFileBuilderWrapper<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            once_inited: Cell::new(false),
+        }
+    }
+}
+
+impl<T: AudioDecoderFileBuilder> IAIMPExtensionAudioDecoderOld
+    for AudioDecoderFileBuilderWrapper<T>
+{
+    unsafe fn create_decoder(
+        &self,
+        file_name: ComRc<dyn IAIMPString>,
+        flags: DecoderFlags,
+        error_info: ComPtr<dyn IAIMPErrorInfo>,
+        decoder: *mut ComRc<dyn IAIMPAudioDecoder>,
+    ) -> HRESULT {
+        create_decoder(
+            &self.once_inited,
+            T::ONLY_INSTANCE,
+            flags,
+            error_info,
+            decoder,
+            || {
+                file_name.add_ref();
+                self.inner.create(AimpString(file_name))
+            },
+        )
+        .hresult()
+    }
+}
+
+impl<T: AudioDecoderFileBuilder> IAIMPExtensionAudioDecoderPriority
+    for AudioDecoderFileBuilderWrapper<T>
+{
+    unsafe fn get_priority(&self) -> i32 {
+        T::PRIORITY.unwrap_or(0)
+    }
+}
+
+impl<T> Extension for AudioDecoderFileBuilderWrapper<T> {
+    const SERVICE_IID: IID = <dyn IAIMPServiceAudioDecoders>::IID;
+}
+
+impl<T: AudioDecoderFileBuilder> From<AudioDecoderFileBuilderWrapper<T>>
+    for ComRc<dyn IAIMPExtensionAudioDecoderOld>
+{
+    fn from(wrapper: AudioDecoderFileBuilderWrapper<T>) -> Self {
+        let wrapper = com_wrapper!(
+            wrapper =>
+            dyn IAIMPExtensionAudioDecoderOld,
+            dyn IAIMPExtensionAudioDecoderPriority
+        );
+        unsafe { wrapper.into_com_rc() }
+    }
+}
+
+impl<T: AudioDecoderFileBuilder> ComInterfaceQuerier for AudioDecoderFileBuilderWrapper<T> {
+    fn query_interface(&self, riid: &IID) -> bool {
+        if riid == &<dyn IAIMPExtensionAudioDecoderPriority>::IID {
+            T::PRIORITY.is_some()
+        } else {
+            true
+        }
+    }
+}
+
 pub(crate) struct AudioDecoders(ComPtr<dyn IAIMPServiceAudioDecoders>);
 
 impl AudioDecoders {
@@ -299,7 +695,7 @@ impl AudioDecoders {
                     decoder.as_mut_ptr(),
                 )
                 .with_error_info(error_info)?;
-            Ok(AimpAudioDecoder(decoder.assume_init()))
+            Ok(AimpAudioDecoder::new(decoder.assume_init()))
         }
     }
 
@@ -315,7 +711,7 @@ impl AudioDecoders {
                     decoder.as_mut_ptr(),
                 )
                 .with_error_info(error_info)?;
-            Ok(AimpAudioDecoder(decoder.assume_init()))
+            Ok(AimpAudioDecoder::new(decoder.assume_init()))
         }
     }
 }
@@ -326,9 +722,92 @@ impl From<ComPtr<dyn IAIMPServiceAudioDecoders>> for AudioDecoders {
     }
 }
 
-pub struct AimpAudioDecoder(ComRc<dyn IAIMPAudioDecoder>);
+fn query<T, U>(object: &ComRc<U>) -> Option<ComRc<T>>
+where
+    T: ComInterface + ?Sized,
+    U: ComInterface + ?Sized,
+{
+    unsafe {
+        let mut ppv = MaybeUninit::uninit();
+        if object.query_interface(&T::IID, ppv.as_mut_ptr()) == S_OK {
+            Some(ComRc::from_ptr(ppv.assume_init() as _))
+        } else {
+            None
+        }
+    }
+}
+
+struct ForwardingListener(ListenerSet);
+
+impl IAIMPAudioDecoderListener for ForwardingListener {
+    unsafe fn changed(&self, changes: DecoderChange) {
+        self.0.notify(changes)
+    }
+}
+
+impl ComInterfaceQuerier for ForwardingListener {}
+
+struct Forwarding {
+    notifications: ComRc<dyn IAIMPAudioDecoderNotifications>,
+    listener: ComRc<dyn IAIMPAudioDecoderListener>,
+    listeners: ListenerSet,
+}
+
+impl Drop for Forwarding {
+    fn drop(&mut self) {
+        unsafe {
+            self.notifications
+                .listener_remove(Clone::clone(&self.listener))
+        }
+    }
+}
+
+/// Decoder created by AIMP
+///
+/// Its format changes are passed to `notifications()` listeners
+/// immediately or when running call of wrapping decoder returns to AIMP
+pub struct AimpAudioDecoder {
+    inner: ComRc<dyn IAIMPAudioDecoder>,
+    buffering_progress: Option<ComRc<dyn IAIMPAudioDecoderBufferingProgress>>,
+    forwarding: Option<Forwarding>,
+}
 
 impl AimpAudioDecoder {
+    pub(crate) fn new(inner: ComRc<dyn IAIMPAudioDecoder>) -> Self {
+        let forwarding =
+            query::<dyn IAIMPAudioDecoderNotifications, _>(&inner).map(|notifications| {
+                let listeners = ListenerSet::new();
+                let listener = unsafe {
+                    com_wrapper!(
+                        ForwardingListener(listeners.clone()) => dyn IAIMPAudioDecoderListener
+                    )
+                    .into_com_rc()
+                };
+                unsafe { notifications.listener_add(Clone::clone(&listener)) }
+                Forwarding {
+                    notifications,
+                    listener,
+                    listeners,
+                }
+            });
+        Self {
+            buffering_progress: query(&inner),
+            forwarding,
+            inner,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn from_rust<T: AudioDecoder>(decoder: T) -> Self {
+        let wrapper = com_wrapper!(
+            AudioDecoderWrapper::new(decoder) =>
+            dyn IAIMPAudioDecoder,
+            dyn IAIMPAudioDecoderBufferingProgress,
+            dyn IAIMPAudioDecoderNotifications
+        );
+        Self::new(unsafe { wrapper.into_com_rc() })
+    }
+
     pub fn from_stream<T: Into<Stream>>(stream: T) -> Result<Self> {
         AUDIO_DECODERS
             .get()
@@ -341,10 +820,10 @@ impl AimpAudioDecoder {
 }
 
 impl AudioDecoder for AimpAudioDecoder {
-    fn file_info(&self) -> Option<FileInfo> {
+    fn file_info(&mut self) -> Option<FileInfo> {
         let info = FileInfo::default();
         unsafe {
-            if self.0.get_file_info(info.prop_list.0.as_raw()) == TRUE {
+            if self.inner.get_file_info(info.prop_list.0.as_raw()) == TRUE {
                 Some(info)
             } else {
                 None
@@ -352,12 +831,12 @@ impl AudioDecoder for AimpAudioDecoder {
         }
     }
 
-    fn stream_info(&self) -> Option<StreamInfo> {
+    fn stream_info(&mut self) -> Option<StreamInfo> {
         let mut sample_rate = MaybeUninit::uninit();
         let mut channels = MaybeUninit::uninit();
         let mut sample_format = MaybeUninit::uninit();
         unsafe {
-            if self.0.get_stream_info(
+            if self.inner.get_stream_info(
                 sample_rate.as_mut_ptr(),
                 channels.as_mut_ptr(),
                 sample_format.as_mut_ptr(),
@@ -374,39 +853,52 @@ impl AudioDecoder for AimpAudioDecoder {
         }
     }
 
-    fn is_seekable(&self) -> bool {
-        unsafe { self.0.is_seekable() == TRUE }
+    fn is_seekable(&mut self) -> bool {
+        unsafe { self.inner.is_seekable() == TRUE }
     }
 
-    fn is_realtime_stream(&self) -> bool {
-        unsafe { self.0.is_realtime_stream() == TRUE }
+    fn is_realtime_stream(&mut self) -> bool {
+        unsafe { self.inner.is_realtime_stream() == TRUE }
     }
 
-    fn available_data(&self) -> i64 {
-        unsafe { self.0.get_available_data() }
+    fn available_data(&mut self) -> i64 {
+        unsafe { self.inner.get_available_data() }
     }
 
-    fn size(&self) -> i64 {
-        unsafe { self.0.get_size() }
+    fn size(&mut self) -> i64 {
+        unsafe { self.inner.get_size() }
     }
 
-    fn pos(&self) -> i64 {
-        unsafe { self.0.get_position() }
+    fn pos(&mut self) -> i64 {
+        unsafe { self.inner.get_position() }
     }
 
-    fn set_pos(&self, pos: i64) -> bool {
-        unsafe { self.0.set_position(pos) == TRUE }
+    fn set_pos(&mut self, pos: i64) -> bool {
+        unsafe { self.inner.set_position(pos) == TRUE }
     }
 
-    fn read(&self, buf: &mut [u8]) -> i32 {
-        unsafe { self.0.read(buf.as_mut_ptr() as *mut _, buf.len() as i32) }
+    fn read(&mut self, buf: &mut [u8]) -> i32 {
+        unsafe {
+            self.inner
+                .read(buf.as_mut_ptr() as *mut _, buf.len() as i32)
+        }
     }
 
     fn buffering_progress(&self) -> Option<BufferingProgress> {
-        None
+        let buffering_progress = self.buffering_progress.as_ref()?;
+        unsafe {
+            let mut value = MaybeUninit::uninit();
+            if buffering_progress.get(value.as_mut_ptr()) == TRUE {
+                Some(value.assume_init())
+            } else {
+                None
+            }
+        }
     }
 
-    fn notifications<'a>(&self) -> Option<&'a AudioDecoderNotificationsWrapper> {
-        None
+    fn notifications(&self) -> Option<AudioDecoderNotificationsWrapper> {
+        self.forwarding
+            .as_ref()
+            .map(|forwarding| forwarding.listeners.clone().into())
     }
 }
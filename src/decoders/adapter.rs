@@ -0,0 +1,472 @@
+use super::{
+    pcm::{decoder_for, encoder_for, DecodeFn, EncodeFn},
+    sample_size, AudioDecoder, AudioDecoderNotificationsWrapper, BufferingProgress, Dither,
+    Gapless, SampleFormat, SampleFormatError, StreamInfo,
+};
+use crate::file::FileInfo;
+use std::{collections::VecDeque, f64::consts::PI};
+
+// taps on each side of output sample when upsampling
+const HALF_TAPS: usize = 16;
+// phases of filter precomputed when resampling ratio allows
+const MAX_TABLE_PHASES: u64 = 1024;
+const CUTOFF: f64 = 0.95;
+
+/// Matrix of gains, output channel `o` is sum of input channels `i` multiplied by `matrix[o][i]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelMap {
+    matrix: Vec<Vec<f64>>,
+}
+
+impl ChannelMap {
+    pub fn new(matrix: Vec<Vec<f64>>) -> Self {
+        Self { matrix }
+    }
+
+    /// Output channel `o` takes input channel `order[o]`
+    pub fn reorder(order: &[usize], inputs: usize) -> Self {
+        let matrix = order
+            .iter()
+            .map(|&input| {
+                let mut row = vec![0.0; inputs];
+                row[input] = 1.0;
+                row
+            })
+            .collect();
+        Self { matrix }
+    }
+
+    pub fn mono(inputs: usize) -> Self {
+        Self {
+            matrix: vec![vec![1.0 / inputs as f64; inputs]],
+        }
+    }
+
+    /// 5.1 in `L R C LFE Ls Rs` order to stereo, LFE is dropped
+    pub fn downmix_5_1() -> Self {
+        let gain = 0.5f64.sqrt();
+        let norm = 1.0 / (1.0 + 2.0 * gain);
+        let row = |front: usize, surround: usize| {
+            let mut row = vec![0.0; 6];
+            row[front] = norm;
+            row[2] = gain * norm;
+            row[surround] = gain * norm;
+            row
+        };
+        Self {
+            matrix: vec![row(0, 4), row(1, 5)],
+        }
+    }
+
+    pub fn inputs(&self) -> usize {
+        self.matrix.first().map_or(0, Vec::len)
+    }
+
+    pub fn outputs(&self) -> usize {
+        self.matrix.len()
+    }
+
+    fn apply(&self, input: &[f64], output: &mut Vec<f64>) {
+        output.extend(self.matrix.iter().map(|row| {
+            row.iter()
+                .zip(input)
+                .map(|(gain, sample)| gain * sample)
+                .sum::<f64>()
+        }));
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Windowed-sinc interpolation between rates reduced by their GCD
+struct Resampler {
+    input: u64,
+    output: u64,
+    half: usize,
+    cutoff: f64,
+    table: Option<Vec<f64>>,
+}
+
+impl Resampler {
+    fn new(input: i32, output: i32) -> Self {
+        let (input, output) = (input as u64, output as u64);
+        let gcd = gcd(input, output);
+        let cutoff = CUTOFF * (output as f64 / input as f64).min(1.0);
+        let mut resampler = Self {
+            input: input / gcd,
+            output: output / gcd,
+            half: (HALF_TAPS as f64 / cutoff).ceil() as usize,
+            cutoff,
+            table: None,
+        };
+        if resampler.output <= MAX_TABLE_PHASES {
+            let mut table = vec![0.0; resampler.output as usize * resampler.taps()];
+            for (phase, weights) in table.chunks_mut(resampler.taps()).enumerate() {
+                resampler.compute(phase as u64, weights);
+            }
+            resampler.table = Some(table);
+        }
+        resampler
+    }
+
+    fn taps(&self) -> usize {
+        self.half * 2
+    }
+
+    /// First input frame used for output frame and phase of output frame between input frames
+    fn locate(&self, frame: u64) -> (i64, u64) {
+        let position = frame as u128 * self.input as u128;
+        let input = (position / self.output as u128) as i64;
+        let phase = (position % self.output as u128) as u64;
+        (input - self.half as i64 + 1, phase)
+    }
+
+    fn compute(&self, phase: u64, weights: &mut [f64]) {
+        let frac = phase as f64 / self.output as f64;
+        for (tap, weight) in weights.iter_mut().enumerate() {
+            let x = frac + self.half as f64 - 1.0 - tap as f64;
+            let u = x / self.half as f64;
+            // Blackman window
+            let window = 0.42 + 0.5 * (PI * u).cos() + 0.08 * (2.0 * PI * u).cos();
+            *weight = self.cutoff * sinc(self.cutoff * x) * window;
+        }
+        let sum = weights.iter().sum::<f64>();
+        weights.iter_mut().for_each(|weight| *weight /= sum);
+    }
+
+    fn weights<'a>(&'a self, phase: u64, scratch: &'a mut Vec<f64>) -> &'a [f64] {
+        match &self.table {
+            Some(table) => &table[phase as usize * self.taps()..][..self.taps()],
+            None => {
+                scratch.resize(self.taps(), 0.0);
+                self.compute(phase, scratch);
+                scratch
+            }
+        }
+    }
+}
+
+/// Converts sample rate, channels and sample format of decoder
+///
+/// Positions and size are in output frames. Format of wrapped decoder is taken once,
+/// so it must not change while decoding
+pub struct ConvertingDecoder<D> {
+    inner: D,
+    input: StreamInfo,
+    input_frame_size: usize,
+    decode: DecodeFn<f64>,
+    output: StreamInfo,
+    output_sample_size: usize,
+    encode: EncodeFn<f64>,
+    channel_map: Option<ChannelMap>,
+    resampler: Option<Resampler>,
+    dither: Option<Dither>,
+    // mapped frames of input starting at `start`, negative frames are silence before the stream
+    frames: VecDeque<f64>,
+    start: i64,
+    pending: Vec<u8>,
+    end: Option<i64>,
+    frame: u64,
+    scratch: Vec<f64>,
+}
+
+impl<D: AudioDecoder> ConvertingDecoder<D> {
+    pub fn new(mut inner: D) -> Result<Self, SampleFormatError> {
+        let input = inner.stream_info().ok_or(SampleFormatError::NoStreamInfo)?;
+        let format = input.sample_format;
+        let sample_size = sample_size(format).ok_or(SampleFormatError::Unsupported(format))?;
+        Ok(Self {
+            inner,
+            input_frame_size: sample_size * input.channels as usize,
+            output: input.clone(),
+            input,
+            decode: decoder_for(format)?,
+            output_sample_size: sample_size,
+            encode: encoder_for(format)?,
+            channel_map: None,
+            resampler: None,
+            dither: None,
+            frames: VecDeque::new(),
+            start: 0,
+            pending: Vec::new(),
+            end: None,
+            frame: 0,
+            scratch: Vec::new(),
+        })
+    }
+
+    pub fn sample_rate(mut self, sample_rate: i32) -> Self {
+        assert!(sample_rate > 0, "sample rate must be positive");
+        self.output.sample_rate = sample_rate;
+        self.resampler = if sample_rate == self.input.sample_rate {
+            None
+        } else {
+            Some(Resampler::new(self.input.sample_rate, sample_rate))
+        };
+        self.reset(0);
+        self
+    }
+
+    /// Panics if inputs of map don't match channels of decoder
+    pub fn channel_map(mut self, map: ChannelMap) -> Self {
+        assert_eq!(
+            map.inputs(),
+            self.input.channels as usize,
+            "channel map inputs must match decoder channels"
+        );
+        self.output.channels = map.outputs() as i32;
+        self.channel_map = Some(map);
+        self.reset(0);
+        self
+    }
+
+    pub fn sample_format(mut self, format: SampleFormat) -> Result<Self, SampleFormatError> {
+        self.output_sample_size =
+            sample_size(format).ok_or(SampleFormatError::Unsupported(format))?;
+        self.encode = encoder_for(format)?;
+        self.output.sample_format = format;
+        Ok(self)
+    }
+
+    pub fn dither(mut self, dither: Dither) -> Self {
+        self.dither = Some(dither);
+        self
+    }
+
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    fn output_frame_size(&self) -> usize {
+        self.output_sample_size * self.output.channels as usize
+    }
+
+    fn input_frames(&mut self) -> Option<i64> {
+        let size = self.inner.size();
+        if size < 0 || self.inner.is_realtime_stream() {
+            None
+        } else {
+            Some(size / self.input_frame_size as i64)
+        }
+    }
+
+    fn output_frames(&mut self) -> Option<u64> {
+        let frames = self.input_frames()? as u128;
+        let frames = match &self.resampler {
+            Some(resampler) => {
+                (frames * resampler.output as u128).div_ceil(resampler.input as u128)
+            }
+            None => frames,
+        };
+        Some(frames as u64)
+    }
+
+    fn locate(&self, frame: u64) -> (i64, u64) {
+        match &self.resampler {
+            Some(resampler) => resampler.locate(frame),
+            None => (frame as i64, 0),
+        }
+    }
+
+    fn taps(&self) -> usize {
+        self.resampler.as_ref().map_or(1, Resampler::taps)
+    }
+
+    fn reset(&mut self, frame: u64) {
+        self.frame = frame;
+        self.start = self.locate(frame).0;
+        self.frames.clear();
+        self.pending.clear();
+        self.end = None;
+    }
+
+    /// Reads whole frames of inner decoder, returns `false` at the end
+    fn pull(&mut self) -> bool {
+        let frame_size = self.input_frame_size;
+        let mut buf = vec![0; frame_size * 256];
+        let offset = self.pending.len();
+        buf[..offset].copy_from_slice(&self.pending);
+        let read = self.inner.read(&mut buf[offset..]);
+        if read <= 0 {
+            return false;
+        }
+        let len = offset + read as usize;
+        let whole = len / frame_size * frame_size;
+        self.pending = buf[whole..len].to_vec();
+
+        let channels = self.input.channels as usize;
+        let mut samples = vec![0.0; whole / frame_size * channels];
+        (self.decode)(&buf[..whole], &mut samples, None);
+        for frame in samples.chunks_exact(channels) {
+            match &self.channel_map {
+                Some(map) => {
+                    let mut mapped = Vec::with_capacity(map.outputs());
+                    map.apply(frame, &mut mapped);
+                    self.frames.extend(mapped);
+                }
+                None => self.frames.extend(frame),
+            }
+        }
+        true
+    }
+
+    /// Makes frames `first..first + count` available, silence is used before and after the stream
+    fn fill(&mut self, first: i64, count: usize) {
+        let channels = self.output.channels as usize;
+        let needed = first + count as i64;
+        loop {
+            let next = self.start + (self.frames.len() / channels) as i64;
+            if next >= needed {
+                break;
+            }
+            let silence = next < 0 || self.end.is_some();
+            if silence || !self.pull() {
+                if !silence {
+                    self.end = Some(next);
+                }
+                self.frames.resize(self.frames.len() + channels, 0.0);
+            }
+        }
+        let drop = (first - self.start) as usize * channels;
+        self.frames.drain(..drop);
+        self.start = first;
+    }
+
+    fn next_frame(&mut self, out: &mut Vec<f64>) -> bool {
+        let (first, phase) = self.locate(self.frame);
+        let taps = self.taps();
+        // input frame at or just before output frame
+        let position = first + (taps as i64 / 2 - 1).max(0);
+        self.fill(first, taps);
+        if matches!(self.end, Some(end) if position >= end) {
+            return false;
+        }
+
+        let channels = self.output.channels as usize;
+        let frames = &self.frames;
+        match &self.resampler {
+            Some(resampler) => {
+                let weights = resampler.weights(phase, &mut self.scratch);
+                for channel in 0..channels {
+                    let sample = weights
+                        .iter()
+                        .enumerate()
+                        .map(|(tap, weight)| weight * frames[tap * channels + channel])
+                        .sum::<f64>();
+                    out.push(sample);
+                }
+            }
+            None => out.extend(frames.range(..channels)),
+        }
+        self.frame += 1;
+        true
+    }
+}
+
+impl<D: AudioDecoder> AudioDecoder for ConvertingDecoder<D> {
+    fn file_info(&mut self) -> Option<FileInfo> {
+        self.inner.file_info()
+    }
+
+    fn stream_info(&mut self) -> Option<StreamInfo> {
+        Some(self.output.clone())
+    }
+
+    fn is_seekable(&mut self) -> bool {
+        self.inner.is_seekable()
+    }
+
+    fn is_realtime_stream(&mut self) -> bool {
+        self.inner.is_realtime_stream()
+    }
+
+    fn size(&mut self) -> i64 {
+        let frame_size = self.output_frame_size() as i64;
+        self.output_frames()
+            .map_or(-1, |frames| frames as i64 * frame_size)
+    }
+
+    fn pos(&mut self) -> i64 {
+        self.frame as i64 * self.output_frame_size() as i64
+    }
+
+    fn set_pos(&mut self, pos: i64) -> bool {
+        let mut frame = pos.max(0) as u64 / self.output_frame_size() as u64;
+        if let Some(frames) = self.output_frames() {
+            frame = frame.min(frames);
+        }
+        let mut first = self.locate(frame).0.max(0);
+        if let Some(frames) = self.input_frames() {
+            first = first.min(frames);
+        }
+        if !self.inner.set_pos(first * self.input_frame_size as i64) {
+            return false;
+        }
+        self.reset(frame);
+        // silence before the stream is not read from decoder
+        self.start = self.start.min(first);
+        true
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> i32 {
+        let frame_size = self.output_frame_size();
+        let mut frames = buf.len() / frame_size;
+        if let Some(total) = self.output_frames() {
+            frames = frames.min(total.saturating_sub(self.frame) as usize);
+        }
+
+        let mut samples = Vec::with_capacity(frames * self.output.channels as usize);
+        for _ in 0..frames {
+            if !self.next_frame(&mut samples) {
+                break;
+            }
+        }
+        let len = samples.len() * self.output_sample_size;
+        (self.encode)(&samples, &mut buf[..len], self.dither.as_mut());
+        len as i32
+    }
+
+    fn buffering_progress(&self) -> Option<BufferingProgress> {
+        self.inner.buffering_progress()
+    }
+
+    fn notifications(&self) -> Option<AudioDecoderNotificationsWrapper> {
+        self.inner.notifications()
+    }
+
+    /// Delay and padding of wrapped decoder in output frames
+    fn gapless(&mut self) -> Gapless {
+        let gapless = self.inner.gapless();
+        match &self.resampler {
+            Some(resampler) => {
+                let scale = |frames: u64| {
+                    ((frames as u128 * resampler.output as u128 + resampler.input as u128 / 2)
+                        / resampler.input as u128) as u64
+                };
+                Gapless {
+                    delay: scale(gapless.delay),
+                    padding: scale(gapless.padding),
+                }
+            }
+            None => gapless,
+        }
+    }
+}
@@ -0,0 +1,409 @@
+use super::{
+    query, AimpAudioDecoder, AudioDecoder, AudioDecoderBuilder, AudioDecoderBuilderWrapper,
+    DecoderChange, StreamInfo,
+};
+use crate::{stream::Stream, ErrorInfo};
+use iaimp::{
+    com_wrapper, ComInterfaceQuerier, ComRc, DecoderFlags, IAIMPAudioDecoderListener,
+    IAIMPAudioDecoderNotifications, IAIMPExtensionAudioDecoder,
+};
+use std::{
+    fmt,
+    mem::MaybeUninit,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use winapi::shared::winerror::{E_PENDING, S_OK};
+
+// odd size to catch decoders returning partial frames
+const READ_FRAMES: usize = 333;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ConformanceFailure {
+    pub check: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for ConformanceFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.check, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, thiserror::Error)]
+pub struct ConformanceReport {
+    pub failures: Vec<ConformanceFailure>,
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} decoder check(s) failed:", self.failures.len())?;
+        for failure in &self.failures {
+            writeln!(f, "  {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+struct CountingListener(Arc<AtomicUsize>);
+
+impl IAIMPAudioDecoderListener for CountingListener {
+    unsafe fn changed(&self, changes: DecoderChange) {
+        if changes.contains(DecoderChange::INPUT_FORMAT) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl ComInterfaceQuerier for CountingListener {}
+
+// removes listener before decoder is dropped or returned
+struct Counting {
+    notifications: ComRc<dyn IAIMPAudioDecoderNotifications>,
+    listener: ComRc<dyn IAIMPAudioDecoderListener>,
+    changes: Arc<AtomicUsize>,
+}
+
+impl Counting {
+    fn changes(&self) -> usize {
+        self.changes.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for Counting {
+    fn drop(&mut self) {
+        unsafe {
+            self.notifications
+                .listener_remove(Clone::clone(&self.listener))
+        }
+    }
+}
+
+/// Checks that decoders of builder follow AIMP contract
+///
+/// Decoders are created the same way AIMP does, each one reads `fixture` from the start
+pub struct DecoderConformance<T> {
+    wrapper: AudioDecoderBuilderWrapper<T>,
+    fixture: Stream,
+    failures: Vec<ConformanceFailure>,
+}
+
+impl<T: AudioDecoderBuilder> DecoderConformance<T> {
+    pub fn new(builder: T, fixture: Stream) -> Self {
+        Self {
+            wrapper: AudioDecoderBuilderWrapper::new(builder),
+            fixture,
+            failures: Vec::new(),
+        }
+    }
+
+    pub fn run(mut self) -> Result<(), ConformanceReport> {
+        if let Some(mut decoder) = self.create_checked() {
+            self.check_reading(&mut decoder);
+        }
+        self.check_seeking();
+        self.check_instances();
+
+        if self.failures.is_empty() {
+            Ok(())
+        } else {
+            Err(ConformanceReport {
+                failures: self.failures,
+            })
+        }
+    }
+
+    fn fail(&mut self, check: &'static str, message: String) {
+        self.failures.push(ConformanceFailure { check, message });
+    }
+
+    fn create(&self, flags: DecoderFlags) -> Result<AimpAudioDecoder, String> {
+        let stream = self.fixture.slice(..);
+        let error_info = ErrorInfo::default();
+        unsafe {
+            let mut decoder = MaybeUninit::uninit();
+            // AIMP lends the stream, wrapper takes its own reference
+            let res = self.wrapper.create_decoder(
+                ComRc::from(stream.0.as_raw()),
+                flags,
+                error_info.0.as_raw(),
+                decoder.as_mut_ptr(),
+            );
+            match res {
+                S_OK => Ok(AimpAudioDecoder::new(decoder.assume_init())),
+                E_PENDING => Err("E_PENDING".to_string()),
+                _ => Err(format!("{:#x}: {}", res, error_info.get().msg)),
+            }
+        }
+    }
+
+    fn create_checked(&mut self) -> Option<AimpAudioDecoder> {
+        match self.create(DecoderFlags::FORCE_CREATE_INSTANCE) {
+            Ok(decoder) => Some(decoder),
+            Err(err) => {
+                self.fail("create", format!("builder failed on fixture with {}", err));
+                None
+            }
+        }
+    }
+
+    fn stream_info(&mut self, decoder: &mut AimpAudioDecoder) -> Option<StreamInfo> {
+        let info = decoder.stream_info();
+        match &info {
+            None => self.fail("stream_info", "decoder has no stream info".to_string()),
+            Some(info) if info.channels <= 0 || info.sample_rate <= 0 => self.fail(
+                "stream_info",
+                format!(
+                    "{} channel(s) at {} Hz is not a valid stream",
+                    info.channels, info.sample_rate
+                ),
+            ),
+            Some(info) if info.frame_size().is_none() => self.fail(
+                "stream_info",
+                format!("sample format {:?} is not supported", info.sample_format),
+            ),
+            Some(_) => return info,
+        }
+        None
+    }
+
+    fn listen(&mut self, decoder: &AimpAudioDecoder) -> Option<Counting> {
+        let notifications = query::<dyn IAIMPAudioDecoderNotifications, _>(&decoder.inner)?;
+        unsafe {
+            let changes = Arc::new(AtomicUsize::new(0));
+            let listener = com_wrapper!(
+                CountingListener(changes.clone()) => dyn IAIMPAudioDecoderListener
+            )
+            .into_com_rc();
+            notifications.listener_add(Clone::clone(&listener));
+            Some(Counting {
+                notifications,
+                listener,
+                changes,
+            })
+        }
+    }
+
+    fn check_reading(&mut self, decoder: &mut AimpAudioDecoder) {
+        let mut info = match self.stream_info(decoder) {
+            Some(info) => info,
+            None => return,
+        };
+        let mut frame_size = info.frame_size().unwrap_or(1);
+        let changes = self.listen(decoder);
+        let mut seen_changes = 0;
+        let realtime = decoder.is_realtime_stream();
+
+        let size = decoder.size();
+        let pos = decoder.pos();
+        if pos != 0 {
+            self.fail(
+                "position",
+                format!("new decoder is at {} instead of 0", pos),
+            );
+        }
+        if !realtime && size % frame_size as i64 != 0 {
+            self.fail(
+                "size",
+                format!(
+                    "size {} is not a multiple of frame size {}",
+                    size, frame_size
+                ),
+            );
+        }
+
+        let mut total = 0;
+        loop {
+            let mut buf = vec![0; READ_FRAMES * frame_size + 1];
+            let read = decoder.read(&mut buf);
+            if read < 0 {
+                self.fail("read", format!("read failed at {} with {}", total, read));
+                break;
+            }
+            if read == 0 {
+                break;
+            }
+            let partial = read as usize % frame_size;
+            if partial > 0 {
+                self.fail(
+                    "read alignment",
+                    format!(
+                        "read returned {} bytes at {}, not whole frames of {} bytes",
+                        read, total, frame_size
+                    ),
+                );
+                break;
+            }
+            total += read as i64;
+
+            let pos = decoder.pos();
+            if !realtime && pos != total {
+                self.fail(
+                    "position",
+                    format!("pos is {} after reading {} bytes", pos, total),
+                );
+                break;
+            }
+            let available = decoder.available_data();
+            if !realtime && available != size - pos {
+                self.fail(
+                    "available_data",
+                    format!(
+                        "available_data is {} but size - pos is {}",
+                        available,
+                        size - pos
+                    ),
+                );
+                break;
+            }
+
+            let new_info = match decoder.stream_info() {
+                Some(new_info) => new_info,
+                None => {
+                    self.fail("stream_info", format!("stream info lost at {}", total));
+                    break;
+                }
+            };
+            if new_info != info {
+                let signalled = changes
+                    .as_ref()
+                    .map(Counting::changes)
+                    .filter(|changes| *changes > seen_changes);
+                match signalled {
+                    Some(changes) => seen_changes = changes,
+                    None => {
+                        self.fail(
+                            "stream_info",
+                            format!(
+                                "stream info changed from {:?} to {:?} at {} without DecoderChange::INPUT_FORMAT",
+                                info, new_info, total
+                            ),
+                        );
+                        break;
+                    }
+                }
+                frame_size = match new_info.frame_size() {
+                    Some(frame_size) => frame_size,
+                    None => {
+                        self.fail(
+                            "stream_info",
+                            format!(
+                                "sample format changed to unsupported {:?} at {}",
+                                new_info.sample_format, total
+                            ),
+                        );
+                        break;
+                    }
+                };
+                info = new_info;
+            }
+        }
+
+        if !realtime && size >= 0 && total != size {
+            self.fail(
+                "read length",
+                format!("read {} bytes in total but size is {}", total, size),
+            );
+        }
+    }
+
+    fn read_to_end(decoder: &mut AimpAudioDecoder, frame_size: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut buf = vec![0; READ_FRAMES * frame_size];
+        loop {
+            match decoder.read(&mut buf) {
+                read if read <= 0 => break data,
+                read => data.extend_from_slice(&buf[..read as usize]),
+            }
+        }
+    }
+
+    fn check_seeking(&mut self) {
+        let mut decoder = match self.create(DecoderFlags::FORCE_CREATE_INSTANCE) {
+            Ok(decoder) => decoder,
+            Err(_) => return,
+        };
+        if !decoder.is_seekable() || decoder.is_realtime_stream() {
+            return;
+        }
+        let frame_size = match decoder.stream_info() {
+            Some(info) if info.channels > 0 => match info.frame_size() {
+                Some(frame_size) => frame_size,
+                None => return,
+            },
+            _ => return,
+        };
+
+        let data = Self::read_to_end(&mut decoder, frame_size);
+        let frames = (data.len() / frame_size) as i64;
+        let positions = [0, frames / 2, frames * 3 / 4, frames]
+            .iter()
+            .map(|frame| frame * frame_size as i64)
+            .collect::<Vec<_>>();
+        for &pos in &positions {
+            if !decoder.set_pos(pos) {
+                self.fail("seek", format!("set_pos({}) failed", pos));
+                continue;
+            }
+            let new_pos = decoder.pos();
+            if new_pos != pos {
+                self.fail("seek", format!("pos is {} after set_pos({})", new_pos, pos));
+                continue;
+            }
+            let tail = Self::read_to_end(&mut decoder, frame_size);
+            if tail[..] != data[pos as usize..] {
+                self.fail(
+                    "seek",
+                    format!("data read after set_pos({}) differs from full read", pos),
+                );
+            }
+        }
+
+        if frame_size > 1 && frames > 1 {
+            let unaligned = positions[1] + 1;
+            if decoder.set_pos(unaligned) && decoder.pos() % frame_size as i64 != 0 {
+                self.fail(
+                    "seek",
+                    format!(
+                        "set_pos({}) landed on {} inside a frame of {} bytes",
+                        unaligned,
+                        decoder.pos(),
+                        frame_size
+                    ),
+                );
+            }
+        }
+    }
+
+    fn check_instances(&mut self) {
+        let first = match self.create(DecoderFlags::FORCE_CREATE_INSTANCE) {
+            Ok(decoder) => decoder,
+            Err(_) => return,
+        };
+        let second = self.create(DecoderFlags::NONE);
+        match (T::ONLY_INSTANCE, second) {
+            (true, Err(err)) if err == "E_PENDING" => {}
+            (true, res) => self.fail(
+                "only instance",
+                format!(
+                    "second decoder must be refused with E_PENDING, got {}",
+                    res.err().unwrap_or_else(|| "decoder".to_string())
+                ),
+            ),
+            (false, Err(err)) => self.fail(
+                "only instance",
+                format!("second decoder failed with {}", err),
+            ),
+            (false, Ok(_)) => {}
+        }
+        if let Err(err) = self.create(DecoderFlags::FORCE_CREATE_INSTANCE) {
+            self.fail(
+                "only instance",
+                format!("forced decoder failed with {}", err),
+            );
+        }
+        drop(first);
+    }
+}
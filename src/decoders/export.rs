@@ -0,0 +1,377 @@
+use super::{
+    sample_size, AimpAudioDecoder, AudioDecoder, SampleFormat, SampleFormatError, StreamInfo,
+};
+use crate::{
+    file::{FileUri, VirtualFile},
+    threading::{TaskHandle, THREADS},
+    ProgressCallback,
+};
+use futures::channel::oneshot;
+use parking_lot::Mutex;
+use std::{
+    convert::TryFrom,
+    io,
+    io::{Seek, SeekFrom, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+const CHUNK_FRAMES: usize = 16 * 1024;
+const FORMAT_PCM: u16 = 0x0001;
+const FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+// tail of `KSDATAFORMAT_SUBTYPE_*` GUIDs after format tag
+const SUBTYPE_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+// `JUNK` chunk reserved for `ds64` if file grows over 4 GiB
+const DS64_SIZE: usize = 28;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error("Failed to create decoder: {0}")]
+    Decoder(#[source] crate::Error),
+    #[error("Decoder has no stream info")]
+    StreamInfo,
+    #[error("{0}")]
+    SampleFormat(
+        #[from]
+        #[source]
+        SampleFormatError,
+    ),
+    #[error("Export was canceled")]
+    Canceled,
+    #[error("{0}")]
+    Io(
+        #[from]
+        #[source]
+        io::Error,
+    ),
+}
+
+/// Range in seconds is relative to the start of track
+#[derive(Debug, Default)]
+pub struct ExportOptions {
+    start: Option<f64>,
+    finish: Option<f64>,
+    progress: Option<ProgressCallback>,
+}
+
+impl ExportOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start(mut self, secs: f64) -> Self {
+        self.start = Some(secs);
+        self
+    }
+
+    pub fn finish(mut self, secs: f64) -> Self {
+        self.finish = Some(secs);
+        self
+    }
+
+    /// Called after every chunk if length of track is known, returns `true` to cancel export
+    pub fn progress(mut self, progress: ProgressCallback) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    fn report(&self, part: Option<f32>) -> bool {
+        match (&self.progress, part) {
+            (Some(progress), Some(part)) => progress.progress(part),
+            _ => false,
+        }
+    }
+
+    // shifts range into clip of virtual file
+    fn clip(&self, clip_start: Option<f64>, clip_finish: Option<f64>) -> (f64, Option<f64>) {
+        let offset = clip_start.unwrap_or(0.0);
+        let start = offset + self.start.unwrap_or(0.0);
+        let finish = match (self.finish.map(|finish| offset + finish), clip_finish) {
+            (Some(finish), Some(clip_finish)) => Some(finish.min(clip_finish)),
+            (finish, clip_finish) => finish.or(clip_finish),
+        };
+        (start, finish)
+    }
+}
+
+fn wav_format(info: &StreamInfo, bytes: usize) -> Vec<u8> {
+    let bits = bytes as u16 * 8;
+    let block_align = (bytes * info.channels as usize) as u16;
+    let tag = match info.sample_format {
+        SampleFormat::ThirtyTwoBitFloat => FORMAT_IEEE_FLOAT,
+        _ => FORMAT_PCM,
+    };
+    let extensible = info.channels > 2 || (tag == FORMAT_PCM && bits > 16);
+
+    let mut fmt = Vec::with_capacity(40);
+    fmt.extend(&if extensible { FORMAT_EXTENSIBLE } else { tag }.to_le_bytes());
+    fmt.extend(&(info.channels as u16).to_le_bytes());
+    fmt.extend(&(info.sample_rate as u32).to_le_bytes());
+    fmt.extend(&(info.sample_rate as u32 * block_align as u32).to_le_bytes());
+    fmt.extend(&block_align.to_le_bytes());
+    fmt.extend(&bits.to_le_bytes());
+    if extensible {
+        fmt.extend(&22u16.to_le_bytes());
+        fmt.extend(&bits.to_le_bytes());
+        // speaker positions are unknown
+        fmt.extend(&0u32.to_le_bytes());
+        fmt.extend(&tag.to_le_bytes());
+        fmt.extend(&SUBTYPE_TAIL);
+    }
+    fmt
+}
+
+fn write_chunk_header<W: Write>(writer: &mut W, id: &[u8; 4], size: u32) -> io::Result<()> {
+    writer.write_all(id)?;
+    writer.write_all(&size.to_le_bytes())
+}
+
+/// Writes header before samples and patches sizes after them
+struct WavWriter<'a, W> {
+    writer: &'a mut W,
+    start: u64,
+    frame_size: usize,
+    len: u64,
+}
+
+impl<'a, W: Write + Seek> WavWriter<'a, W> {
+    fn new(writer: &'a mut W, info: &StreamInfo, sample_size: usize) -> io::Result<Self> {
+        let start = writer.stream_position()?;
+        let fmt = wav_format(info, sample_size);
+        write_chunk_header(writer, b"RIFF", 0)?;
+        writer.write_all(b"WAVE")?;
+        write_chunk_header(writer, b"JUNK", DS64_SIZE as u32)?;
+        writer.write_all(&[0; DS64_SIZE])?;
+        write_chunk_header(writer, b"fmt ", fmt.len() as u32)?;
+        writer.write_all(&fmt)?;
+        write_chunk_header(writer, b"data", 0)?;
+        Ok(Self {
+            writer,
+            start,
+            frame_size: sample_size * info.channels as usize,
+            len: 0,
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(data)?;
+        self.len += data.len() as u64;
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<u64> {
+        if self.len % 2 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+        let end = self.writer.stream_position()?;
+        let riff_size = end - self.start - 8;
+        let frames = self.len / self.frame_size as u64;
+
+        self.writer.seek(SeekFrom::Start(self.start))?;
+        match (u32::try_from(riff_size), u32::try_from(self.len)) {
+            (Ok(riff_size), Ok(len)) => {
+                write_chunk_header(self.writer, b"RIFF", riff_size)?;
+                self.writer
+                    .seek(SeekFrom::Start(end - self.len - self.len % 2 - 4))?;
+                self.writer.write_all(&len.to_le_bytes())?;
+            }
+            _ => {
+                write_chunk_header(self.writer, b"RF64", u32::MAX)?;
+                self.writer.seek(SeekFrom::Current(4))?;
+                write_chunk_header(self.writer, b"ds64", DS64_SIZE as u32)?;
+                self.writer.write_all(&riff_size.to_le_bytes())?;
+                self.writer.write_all(&self.len.to_le_bytes())?;
+                self.writer.write_all(&frames.to_le_bytes())?;
+                self.writer.write_all(&0u32.to_le_bytes())?;
+                self.writer
+                    .seek(SeekFrom::Start(end - self.len - self.len % 2 - 4))?;
+                self.writer.write_all(&u32::MAX.to_le_bytes())?;
+            }
+        }
+        self.writer.seek(SeekFrom::Start(end))?;
+        Ok(frames)
+    }
+}
+
+// `progress` gets part of written samples if length is known and returns `true` to cancel export
+fn write_wav<D, W, F>(
+    decoder: &mut D,
+    writer: &mut W,
+    start: f64,
+    finish: Option<f64>,
+    mut progress: F,
+) -> Result<u64, ExportError>
+where
+    D: AudioDecoder,
+    W: Write + Seek,
+    F: FnMut(Option<f32>) -> bool,
+{
+    let info = decoder.stream_info().ok_or(ExportError::StreamInfo)?;
+    let sample_size = sample_size(info.sample_format)
+        .ok_or(SampleFormatError::Unsupported(info.sample_format))?;
+    let frame_size = sample_size * info.channels as usize;
+    let to_bytes =
+        |secs: f64| (secs * info.sample_rate as f64).round().max(0.0) as u64 * frame_size as u64;
+
+    let gapless = decoder.gapless();
+    let delay = gapless.delay * frame_size as u64;
+    let padding = gapless.padding * frame_size as u64;
+
+    let mut buf = vec![0; CHUNK_FRAMES * frame_size];
+    let first = to_bytes(start) + delay;
+    let mut skip = first;
+    if skip > 0 && decoder.is_seekable() && decoder.set_pos(skip as i64) {
+        skip = 0;
+    }
+    // decoder is not seekable, so start is reached by reading
+    while skip > 0 {
+        let len = skip.min(buf.len() as u64) as usize;
+        let read = decoder.read(&mut buf[..len]);
+        if read <= 0 {
+            break;
+        }
+        skip -= read as u64;
+    }
+    let size = decoder.size();
+    let last = (size >= 0).then(|| (size as u64).saturating_sub(padding));
+    let mut remaining = match (finish.map(|finish| to_bytes(finish) + delay), last) {
+        (Some(finish), Some(last)) => Some(finish.min(last)),
+        (finish, last) => finish.or(last),
+    }
+    .map(|end| end.saturating_sub(first));
+    let total = remaining;
+
+    let mut wav = WavWriter::new(writer, &info, sample_size)?;
+    while remaining != Some(0) {
+        let len = remaining.map_or(buf.len(), |remaining| {
+            remaining.min(buf.len() as u64) as usize
+        });
+        let read = decoder.read(&mut buf[..len]);
+        if read <= 0 {
+            break;
+        }
+        let data = &buf[..read as usize];
+        wav.write(data)?;
+        if let Some(remaining) = &mut remaining {
+            *remaining -= data.len() as u64;
+        }
+        let part = total
+            .filter(|total| *total > 0)
+            .map(|total| wav.len as f32 / total as f32);
+        if progress(part) {
+            return Err(ExportError::Canceled);
+        }
+    }
+    Ok(wav.finish()?)
+}
+
+/// Writes samples of decoder as WAV file, or RF64 if it exceeds 4 GiB, returns number of frames
+///
+/// Header is based on format at the start, so decoder must not change it while decoding.
+/// Delay and padding from [`AudioDecoder::gapless`] are not written
+pub fn export_decoder<D, W>(
+    decoder: &mut D,
+    writer: &mut W,
+    options: &ExportOptions,
+) -> Result<u64, ExportError>
+where
+    D: AudioDecoder,
+    W: Write + Seek,
+{
+    let (start, finish) = options.clip(None, None);
+    write_wav(decoder, writer, start, finish, |part| options.report(part))
+}
+
+fn export_file_uri<W, F>(
+    file_uri: &FileUri,
+    writer: &mut W,
+    options: &ExportOptions,
+    canceled: F,
+) -> Result<u64, ExportError>
+where
+    W: Write + Seek,
+    F: Fn() -> bool,
+{
+    // tracks of CUE sheets and other containers are clips of source file
+    let (source, (start, finish)) = match VirtualFile::from_file_uri(file_uri.clone()) {
+        Some(file) => (
+            file.audio_source_file()
+                .map_or_else(|| file_uri.clone(), FileUri::from),
+            options.clip(file.clip_start(), file.clip_finish()),
+        ),
+        None => (file_uri.clone(), options.clip(None, None)),
+    };
+    let mut decoder = AimpAudioDecoder::from_file_uri(&source).map_err(ExportError::Decoder)?;
+    write_wav(&mut decoder, writer, start, finish, |part| {
+        options.report(part) || canceled()
+    })
+}
+
+struct SendExport(String, ExportOptions);
+
+// file URI is sent as string and progress callback can be called from any thread
+unsafe impl Send for SendExport {}
+
+/// Decodes file on `THREADS` and writes it as WAV file like [`export_decoder`]
+///
+/// Virtual files such as CUE tracks are clipped to their bounds
+pub fn export<W>(file_uri: &FileUri, writer: W, options: ExportOptions) -> ExportTask<W>
+where
+    W: Write + Seek + Send + 'static,
+{
+    let canceled = Arc::new(AtomicBool::new(false));
+    let task_canceled = canceled.clone();
+    let export = SendExport(file_uri.to_string(), options);
+    // kept outside of the task, so it is returned even if task never runs
+    let writer = Arc::new(Mutex::new(Some(writer)));
+    let task_writer = writer.clone();
+    let (tx, rx) = oneshot::channel();
+
+    let handle = THREADS.get().spawn(async move {
+        let export = export;
+        let mut writer = task_writer.lock();
+        let res = export_file_uri(
+            &FileUri::from(export.0.as_str()),
+            writer.as_mut().unwrap(),
+            &export.1,
+            || task_canceled.load(Ordering::Relaxed),
+        );
+        let _ = tx.send(res);
+    });
+
+    ExportTask {
+        writer,
+        rx,
+        handle,
+        canceled,
+    }
+}
+
+pub struct ExportTask<W> {
+    writer: Arc<Mutex<Option<W>>>,
+    rx: oneshot::Receiver<Result<u64, ExportError>>,
+    handle: TaskHandle,
+    canceled: Arc<AtomicBool>,
+}
+
+impl<W> ExportTask<W> {
+    /// Task stops after current chunk, `wait` returns `Canceled` error then
+    pub fn cancel(&self) {
+        self.canceled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns writer and number of written frames
+    ///
+    /// Result is `Canceled` error if AIMP canceled task before it started
+    pub fn wait(self) -> (W, Result<u64, ExportError>) {
+        self.handle.wait();
+        let res =
+            futures::executor::block_on(self.rx).unwrap_or_else(|_| Err(ExportError::Canceled));
+        (self.writer.lock().take().unwrap(), res)
+    }
+}
@@ -0,0 +1,311 @@
+pub use aiff::{AiffBuilder, AiffFileFormat};
+pub use wav::{WavBuilder, WavFileFormat};
+
+mod aiff;
+mod wav;
+
+use super::{
+    sample_size, AudioDecoder, AudioDecoderBuilder, AudioDecoderNotificationsWrapper,
+    BufferingProgress, DecoderError, SampleFormat, StreamInfo,
+};
+use crate::{file::FileInfo, stream::Stream, AimpString};
+use std::{
+    io,
+    io::{Read, Seek, SeekFrom},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum PcmError {
+    #[error("Stream is not a {0} file")]
+    Format(&'static str),
+    #[error("Unsupported {0} encoding: {1}")]
+    Encoding(&'static str, String),
+    #[error("Malformed {0} file: {1}")]
+    Malformed(&'static str, &'static str),
+    #[error("{0}")]
+    Io(
+        #[from]
+        #[source]
+        io::Error,
+    ),
+}
+
+impl DecoderError for PcmError {
+    fn is_unsupported(&self) -> bool {
+        matches!(self, PcmError::Format(_) | PcmError::Encoding(..))
+    }
+}
+
+fn sample_format(bytes: usize, float: bool) -> Option<SampleFormat> {
+    match (bytes, float) {
+        (1, false) => Some(SampleFormat::EightBit),
+        (2, false) => Some(SampleFormat::SixteenBit),
+        (3, false) => Some(SampleFormat::TwentyFourBit),
+        (4, false) => Some(SampleFormat::ThirtyTwoBit),
+        (4, true) => Some(SampleFormat::ThirtyTwoBitFloat),
+        _ => None,
+    }
+}
+
+/// Returns `false` if stream ended before `buf` was filled
+fn read_header(stream: &mut Stream, buf: &mut [u8]) -> io::Result<bool> {
+    match stream.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+struct Chunk {
+    id: [u8; 4],
+    offset: u64,
+    size: u64,
+}
+
+impl Chunk {
+    // RIFF and IFF chunks are both `id, size, data`, padded to even size
+    fn next(stream: &mut Stream, big_endian: bool) -> io::Result<Option<Chunk>> {
+        let mut header = [0; 8];
+        if !read_header(stream, &mut header)? {
+            return Ok(None);
+        }
+        let mut id = [0; 4];
+        id.copy_from_slice(&header[..4]);
+        let mut size = [0; 4];
+        size.copy_from_slice(&header[4..]);
+        let size = if big_endian {
+            u32::from_be_bytes(size)
+        } else {
+            u32::from_le_bytes(size)
+        };
+        Ok(Some(Chunk {
+            id,
+            offset: stream.pos() as u64,
+            size: size as u64,
+        }))
+    }
+
+    fn read(&self, stream: &mut Stream, len: usize) -> io::Result<Vec<u8>> {
+        let mut data = vec![0; len.min(self.size as usize)];
+        stream.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn skip(&self, stream: &mut Stream) -> io::Result<()> {
+        let end = self.offset + self.size + (self.size & 1);
+        stream.seek(SeekFrom::Start(end))?;
+        Ok(())
+    }
+}
+
+/// Where samples lie in stream and how to convert them to AIMP format
+struct Layout {
+    info: StreamInfo,
+    bits: i32,
+    offset: u64,
+    len: u64,
+    big_endian: bool,
+    signed_bytes: bool,
+    codec: &'static str,
+}
+
+impl Layout {
+    fn to_native(&self, buf: &mut [u8], size: usize) {
+        if self.big_endian && size > 1 {
+            for sample in buf.chunks_exact_mut(size) {
+                sample.reverse();
+            }
+        }
+        // AIMP takes 8-bit samples as unsigned
+        if self.signed_bytes && size == 1 {
+            for sample in buf {
+                *sample ^= 0x80;
+            }
+        }
+    }
+}
+
+/// Decoder of uncompressed PCM created by `WavBuilder`, `AiffBuilder` and `RawPcmBuilder`
+pub struct PcmDecoder {
+    stream: Stream,
+    layout: Layout,
+    sample_size: usize,
+    pos: u64,
+}
+
+impl PcmDecoder {
+    fn new(mut stream: Stream, mut layout: Layout) -> Result<Self, PcmError> {
+        let format = layout.info.sample_format;
+        let sample_size = sample_size(format).ok_or_else(|| {
+            PcmError::Encoding(layout.codec, format!("sample format {:?}", format))
+        })?;
+        let size = stream.size();
+        if size >= 0 {
+            let available = (size as u64).saturating_sub(layout.offset);
+            layout.len = layout.len.min(available);
+        }
+        let frame_size = (sample_size * layout.info.channels as usize) as u64;
+        layout.len = layout.len / frame_size * frame_size;
+        stream.seek(SeekFrom::Start(layout.offset))?;
+        Ok(Self {
+            stream,
+            layout,
+            sample_size,
+            pos: 0,
+        })
+    }
+
+    fn frame_size(&self) -> usize {
+        self.sample_size * self.layout.info.channels as usize
+    }
+
+    pub fn duration(&self) -> f64 {
+        let frames = self.layout.len / self.frame_size() as u64;
+        frames as f64 / self.layout.info.sample_rate as f64
+    }
+
+    pub fn into_inner(self) -> Stream {
+        self.stream
+    }
+}
+
+impl AudioDecoder for PcmDecoder {
+    fn file_info(&mut self) -> Option<FileInfo> {
+        let layout = &self.layout;
+        let info = &layout.info;
+        let bit_rate = info.sample_rate as i64 * info.channels as i64 * layout.bits as i64 / 1000;
+
+        let mut file_info = FileInfo::default();
+        file_info
+            .update()
+            .duration(self.duration())
+            .sample_rate(info.sample_rate)
+            .channels(info.channels)
+            .bit_depth(layout.bits)
+            .bit_rate(bit_rate as i32)
+            .codec(AimpString::from(layout.codec))
+            .file_size(self.stream.size());
+        Some(file_info)
+    }
+
+    fn stream_info(&mut self) -> Option<StreamInfo> {
+        Some(self.layout.info.clone())
+    }
+
+    fn is_seekable(&mut self) -> bool {
+        true
+    }
+
+    fn is_realtime_stream(&mut self) -> bool {
+        false
+    }
+
+    fn size(&mut self) -> i64 {
+        self.layout.len as i64
+    }
+
+    fn pos(&mut self) -> i64 {
+        self.pos as i64
+    }
+
+    fn set_pos(&mut self, pos: i64) -> bool {
+        let frame_size = self.frame_size() as u64;
+        let pos = (pos.max(0) as u64).min(self.layout.len) / frame_size * frame_size;
+        match self.stream.seek(SeekFrom::Start(self.layout.offset + pos)) {
+            Ok(_) => {
+                self.pos = pos;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> i32 {
+        let frame_size = self.frame_size();
+        let remaining = self.layout.len - self.pos;
+        let len = (buf.len() as u64).min(remaining) as usize / frame_size * frame_size;
+
+        let mut read = 0;
+        while read < len {
+            match self.stream.read(&mut buf[read..len]) {
+                Ok(0) => break,
+                Ok(count) => read += count,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return -1,
+            }
+        }
+        // truncated stream, keep position on frame boundary
+        let partial = read % frame_size;
+        if partial > 0 {
+            read -= partial;
+            if self
+                .stream
+                .seek(SeekFrom::Current(-(partial as i64)))
+                .is_err()
+            {
+                return -1;
+            }
+        }
+
+        self.layout.to_native(&mut buf[..read], self.sample_size);
+        self.pos += read as u64;
+        read as i32
+    }
+
+    fn buffering_progress(&self) -> Option<BufferingProgress> {
+        None
+    }
+
+    fn notifications(&self) -> Option<AudioDecoderNotificationsWrapper> {
+        None
+    }
+}
+
+/// Builder of headerless little-endian PCM in given format
+///
+/// Accepts any stream, route it with `DecoderRouter` and `Probe::extension` to limit that
+pub struct RawPcmBuilder {
+    info: StreamInfo,
+}
+
+impl RawPcmBuilder {
+    pub fn new(info: StreamInfo) -> Self {
+        Self { info }
+    }
+}
+
+impl AudioDecoderBuilder for RawPcmBuilder {
+    const PRIORITY: Option<i32> = None;
+    const ONLY_INSTANCE: bool = false;
+
+    type Decoder = PcmDecoder;
+    type Error = PcmError;
+
+    fn create(&self, stream: Stream) -> Result<Self::Decoder, Self::Error> {
+        if self.info.channels <= 0 || self.info.sample_rate <= 0 {
+            return Err(PcmError::Malformed("raw PCM", "invalid stream info"));
+        }
+        let bits = match self.info.sample_format {
+            SampleFormat::ThirtyTwoBitFloat => 32,
+            format => match sample_size(format) {
+                Some(size) => size as i32 * 8,
+                None => {
+                    return Err(PcmError::Encoding(
+                        "raw PCM",
+                        format!("sample format {:?}", format),
+                    ))
+                }
+            },
+        };
+        let layout = Layout {
+            info: self.info.clone(),
+            bits,
+            offset: stream.pos().max(0) as u64,
+            len: u64::MAX,
+            big_endian: false,
+            signed_bytes: false,
+            codec: "PCM",
+        };
+        PcmDecoder::new(stream, layout)
+    }
+}
@@ -0,0 +1,153 @@
+use super::{read_header, sample_format, Chunk, Layout, PcmDecoder, PcmError};
+use crate::{
+    decoders::{AudioDecoderBuilder, StreamInfo},
+    file::{FileFormat, FileFormatsCategory},
+    stream::Stream,
+};
+use std::convert::TryInto;
+
+const NAME: &str = "AIFF";
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// 80-bit IEEE 754 extended precision number
+fn extended_at(data: &[u8], offset: usize) -> f64 {
+    let exponent = i32::from(data[offset] & 0x7F) << 8 | i32::from(data[offset + 1]);
+    let mantissa = u64::from_be_bytes(data[offset + 2..offset + 10].try_into().unwrap());
+    let value = mantissa as f64 * 2f64.powi(exponent - 16383 - 63);
+    if data[offset] & 0x80 != 0 {
+        -value
+    } else {
+        value
+    }
+}
+
+struct Common {
+    info: StreamInfo,
+    bits: i32,
+    frames: u64,
+    big_endian: bool,
+    signed_bytes: bool,
+    codec: &'static str,
+}
+
+fn parse_common(comm: &[u8], aifc: bool) -> Result<Common, PcmError> {
+    if comm.len() < 18 || (aifc && comm.len() < 22) {
+        return Err(PcmError::Malformed(NAME, "`COMM` chunk is too short"));
+    }
+    let channels = u16::from_be_bytes([comm[0], comm[1]]) as usize;
+    let frames = u32_at(comm, 2) as u64;
+    let bits = u16::from_be_bytes([comm[6], comm[7]]) as usize;
+    let sample_rate = extended_at(comm, 8).round();
+
+    let compression = if aifc { &comm[18..22] } else { b"NONE" };
+    let (float, big_endian, signed_bytes, codec) = match compression {
+        b"NONE" | b"twos" => (false, true, true, "PCM"),
+        b"sowt" => (false, false, true, "PCM"),
+        b"raw " => (false, true, false, "PCM"),
+        b"fl32" | b"FL32" => (true, true, false, "IEEE Float"),
+        compression => {
+            return Err(PcmError::Encoding(
+                NAME,
+                format!(
+                    "compression type `{}`",
+                    String::from_utf8_lossy(compression)
+                ),
+            ))
+        }
+    };
+
+    if channels == 0 || !(1.0..=i32::MAX as f64).contains(&sample_rate) {
+        return Err(PcmError::Malformed(NAME, "invalid `COMM` chunk"));
+    }
+    let bytes = if float { 4 } else { bits.div_ceil(8) };
+    let sample_format = sample_format(bytes, float)
+        .ok_or_else(|| PcmError::Encoding(NAME, format!("{}-bit samples", bits)))?;
+    Ok(Common {
+        info: StreamInfo {
+            sample_rate: sample_rate as i32,
+            channels: channels as i32,
+            sample_format,
+        },
+        bits: if float { 32 } else { bits as i32 },
+        frames,
+        big_endian,
+        signed_bytes,
+        codec,
+    })
+}
+
+fn parse(stream: &mut Stream) -> Result<Layout, PcmError> {
+    let mut header = [0; 12];
+    if !read_header(stream, &mut header)? || &header[..4] != b"FORM" {
+        return Err(PcmError::Format(NAME));
+    }
+    let aifc = match &header[8..] {
+        b"AIFF" => false,
+        b"AIFC" => true,
+        _ => return Err(PcmError::Format(NAME)),
+    };
+
+    let mut common = None;
+    let mut data = None;
+    while let Some(chunk) = Chunk::next(stream, true)? {
+        match &chunk.id {
+            b"COMM" => common = Some(parse_common(&chunk.read(stream, 22)?, aifc)?),
+            b"SSND" => {
+                let ssnd = chunk.read(stream, 8)?;
+                if ssnd.len() < 8 {
+                    return Err(PcmError::Malformed(NAME, "`SSND` chunk is too short"));
+                }
+                let offset = u32_at(&ssnd, 0) as u64;
+                let len = chunk.size.saturating_sub(8 + offset);
+                data = Some((chunk.offset + 8 + offset, len));
+                if common.is_some() {
+                    break;
+                }
+            }
+            _ => {}
+        }
+        chunk.skip(stream)?;
+    }
+
+    let common = common.ok_or(PcmError::Malformed(NAME, "`COMM` chunk is missing"))?;
+    let (offset, len) = data.ok_or(PcmError::Malformed(NAME, "`SSND` chunk is missing"))?;
+    // unsupported format is rejected by `PcmDecoder::new`
+    let frame_size = common.info.frame_size().unwrap_or(0) as u64;
+    let len = len.min(common.frames * frame_size);
+    Ok(Layout {
+        info: common.info,
+        bits: common.bits,
+        offset,
+        len,
+        big_endian: common.big_endian,
+        signed_bytes: common.signed_bytes,
+        codec: common.codec,
+    })
+}
+
+/// Builder of decoders of AIFF and uncompressed AIFC files
+pub struct AiffBuilder;
+
+impl AudioDecoderBuilder for AiffBuilder {
+    const PRIORITY: Option<i32> = None;
+    const ONLY_INSTANCE: bool = false;
+
+    type Decoder = PcmDecoder;
+    type Error = PcmError;
+
+    fn create(&self, mut stream: Stream) -> Result<Self::Decoder, Self::Error> {
+        let layout = parse(&mut stream)?;
+        PcmDecoder::new(stream, layout)
+    }
+}
+
+pub struct AiffFileFormat;
+
+impl FileFormat for AiffFileFormat {
+    const DESCRIPTION: &'static str = "Audio Interchange File Format";
+    const EXTS: &'static [&'static str] = &["*.aif", "*.aiff", "*.aifc"];
+    const FLAGS: FileFormatsCategory = FileFormatsCategory::AUDIO;
+}
@@ -0,0 +1,168 @@
+use super::{read_header, sample_format, Chunk, Layout, PcmDecoder, PcmError};
+use crate::{
+    decoders::{AudioDecoderBuilder, StreamInfo},
+    file::{FileFormat, FileFormatsCategory},
+    stream::Stream,
+};
+use std::convert::TryInto;
+
+const NAME: &str = "WAV";
+const FORMAT_PCM: u16 = 0x0001;
+const FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+// RF64 `data` chunk stores this size, real one is in `ds64` chunk
+const RF64_SIZE: u64 = 0xFFFF_FFFF;
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+struct Format {
+    info: StreamInfo,
+    bits: i32,
+    float: bool,
+}
+
+fn parse_format(fmt: &[u8]) -> Result<Format, PcmError> {
+    if fmt.len() < 16 {
+        return Err(PcmError::Malformed(NAME, "`fmt ` chunk is too short"));
+    }
+    let mut tag = u16_at(fmt, 0);
+    let channels = u16_at(fmt, 2) as usize;
+    let sample_rate = u32_at(fmt, 4);
+    let block_align = u16_at(fmt, 12) as usize;
+    let mut bits = u16_at(fmt, 14) as i32;
+
+    if tag == FORMAT_EXTENSIBLE {
+        if fmt.len() < 40 {
+            return Err(PcmError::Malformed(NAME, "`fmt ` chunk is too short"));
+        }
+        // container may be wider than valid bits, e.g. 20 bits in 24
+        let valid_bits = u16_at(fmt, 18) as i32;
+        if valid_bits > 0 {
+            bits = valid_bits;
+        }
+        // sub format GUID starts with format tag
+        tag = u16_at(fmt, 24);
+    }
+    let float = match tag {
+        FORMAT_PCM => false,
+        FORMAT_IEEE_FLOAT => true,
+        tag => return Err(PcmError::Encoding(NAME, format!("format tag {:#06x}", tag))),
+    };
+
+    if channels == 0 || sample_rate == 0 {
+        return Err(PcmError::Malformed(NAME, "invalid `fmt ` chunk"));
+    }
+    let bytes = block_align / channels;
+    if bytes * channels != block_align {
+        return Err(PcmError::Malformed(
+            NAME,
+            "block align is not multiple of channels",
+        ));
+    }
+    let sample_format = sample_format(bytes, float).ok_or_else(|| {
+        PcmError::Encoding(
+            NAME,
+            format!(
+                "{}-byte {} samples",
+                bytes,
+                if float { "float" } else { "integer" }
+            ),
+        )
+    })?;
+    Ok(Format {
+        info: StreamInfo {
+            sample_rate: sample_rate as i32,
+            channels: channels as i32,
+            sample_format,
+        },
+        bits,
+        float,
+    })
+}
+
+fn parse(stream: &mut Stream) -> Result<Layout, PcmError> {
+    let mut header = [0; 12];
+    if !read_header(stream, &mut header)? || &header[8..] != b"WAVE" {
+        return Err(PcmError::Format(NAME));
+    }
+    let rf64 = match &header[..4] {
+        b"RIFF" => false,
+        b"RF64" => true,
+        _ => return Err(PcmError::Format(NAME)),
+    };
+
+    let mut ds64_data_size = None;
+    let mut format = None;
+    let mut data = None;
+    while let Some(mut chunk) = Chunk::next(stream, false)? {
+        match &chunk.id {
+            b"ds64" if rf64 => {
+                let ds64 = chunk.read(stream, 24)?;
+                if ds64.len() < 16 {
+                    return Err(PcmError::Malformed(NAME, "`ds64` chunk is too short"));
+                }
+                ds64_data_size = Some(u64_at(&ds64, 8));
+            }
+            b"fmt " => format = Some(parse_format(&chunk.read(stream, 40)?)?),
+            b"data" => {
+                if rf64 && chunk.size == RF64_SIZE {
+                    chunk.size = ds64_data_size
+                        .ok_or(PcmError::Malformed(NAME, "`ds64` chunk is missing"))?;
+                }
+                data = Some((chunk.offset, chunk.size));
+                // `fmt ` usually precedes `data`, otherwise look for it after samples
+                if format.is_some() {
+                    break;
+                }
+            }
+            _ => {}
+        }
+        chunk.skip(stream)?;
+    }
+
+    let format = format.ok_or(PcmError::Malformed(NAME, "`fmt ` chunk is missing"))?;
+    let (offset, len) = data.ok_or(PcmError::Malformed(NAME, "`data` chunk is missing"))?;
+    Ok(Layout {
+        info: format.info,
+        bits: format.bits,
+        offset,
+        len,
+        big_endian: false,
+        signed_bytes: false,
+        codec: if format.float { "IEEE Float" } else { "PCM" },
+    })
+}
+
+/// Builder of decoders of RIFF and RF64 WAVE files with PCM, IEEE float or extensible format
+pub struct WavBuilder;
+
+impl AudioDecoderBuilder for WavBuilder {
+    const PRIORITY: Option<i32> = None;
+    const ONLY_INSTANCE: bool = false;
+
+    type Decoder = PcmDecoder;
+    type Error = PcmError;
+
+    fn create(&self, mut stream: Stream) -> Result<Self::Decoder, Self::Error> {
+        let layout = parse(&mut stream)?;
+        PcmDecoder::new(stream, layout)
+    }
+}
+
+pub struct WavFileFormat;
+
+impl FileFormat for WavFileFormat {
+    const DESCRIPTION: &'static str = "Waveform Audio";
+    const EXTS: &'static [&'static str] = &["*.wav", "*.wave", "*.rf64"];
+    const FLAGS: FileFormatsCategory = FileFormatsCategory::AUDIO;
+}
@@ -0,0 +1,162 @@
+use super::{AudioDecoder, AudioDecoderNotificationsWrapper, BufferingProgress, StreamInfo};
+use crate::file::FileInfo;
+
+/// Frames added by encoder that are not played, e.g. from LAME header, `iTunSMPB` or Opus pre-skip
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Gapless {
+    /// Encoder delay at the start
+    pub delay: u64,
+    /// Padding at the end
+    pub padding: u64,
+}
+
+/// Hides delay and padding of decoder, they are taken once when first needed
+pub(crate) struct Trimmed<D> {
+    inner: D,
+    // delay and padding in bytes
+    trim: Option<(u64, u64)>,
+    pos: u64,
+    // bytes of delay to read before first samples
+    skip: Option<u64>,
+}
+
+impl<D: AudioDecoder> Trimmed<D> {
+    pub(crate) fn new(inner: D) -> Self {
+        Self {
+            inner,
+            trim: None,
+            pos: 0,
+            skip: None,
+        }
+    }
+
+    fn trim(&mut self) -> (u64, u64) {
+        if let Some(trim) = self.trim {
+            return trim;
+        }
+        let gapless = self.inner.gapless();
+        let trim = match self.inner.stream_info().and_then(|info| info.frame_size()) {
+            Some(frame_size) => {
+                let frame_size = frame_size as u64;
+                (gapless.delay * frame_size, gapless.padding * frame_size)
+            }
+            None => (0, 0),
+        };
+        self.trim = Some(trim);
+        trim
+    }
+
+    fn passthrough(&mut self) -> bool {
+        self.trim() == (0, 0)
+    }
+
+    fn trimmed_size(&mut self) -> Option<u64> {
+        let size = self.inner.size();
+        if size < 0 {
+            return None;
+        }
+        let (delay, padding) = self.trim();
+        Some((size as u64).saturating_sub(delay + padding))
+    }
+}
+
+impl<D: AudioDecoder> AudioDecoder for Trimmed<D> {
+    fn file_info(&mut self) -> Option<FileInfo> {
+        self.inner.file_info()
+    }
+
+    fn stream_info(&mut self) -> Option<StreamInfo> {
+        self.inner.stream_info()
+    }
+
+    fn is_seekable(&mut self) -> bool {
+        self.inner.is_seekable()
+    }
+
+    fn is_realtime_stream(&mut self) -> bool {
+        self.inner.is_realtime_stream()
+    }
+
+    fn available_data(&mut self) -> i64 {
+        if self.passthrough() {
+            return self.inner.available_data();
+        }
+        self.size() - self.pos()
+    }
+
+    fn size(&mut self) -> i64 {
+        if self.passthrough() {
+            return self.inner.size();
+        }
+        self.trimmed_size().map_or(-1, |size| size as i64)
+    }
+
+    fn pos(&mut self) -> i64 {
+        if self.passthrough() {
+            return self.inner.pos();
+        }
+        self.pos as i64
+    }
+
+    fn set_pos(&mut self, pos: i64) -> bool {
+        if self.passthrough() {
+            return self.inner.set_pos(pos);
+        }
+        let (delay, _) = self.trim();
+        let mut pos = pos.max(0) as u64;
+        if let Some(size) = self.trimmed_size() {
+            pos = pos.min(size);
+        }
+        if !self.inner.set_pos((pos + delay) as i64) {
+            return false;
+        }
+        // decoder may round position down to frame
+        let inner_pos = self.inner.pos().max(0) as u64;
+        self.pos = inner_pos.saturating_sub(delay);
+        self.skip = Some(delay.saturating_sub(inner_pos));
+        true
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> i32 {
+        if self.passthrough() {
+            return self.inner.read(buf);
+        }
+        let (delay, _) = self.trim();
+        let mut skip = self.skip.unwrap_or(delay);
+        while skip > 0 {
+            let len = skip.min(buf.len() as u64) as usize;
+            let read = self.inner.read(&mut buf[..len]);
+            if read <= 0 {
+                self.skip = Some(skip);
+                return read;
+            }
+            skip -= read as u64;
+        }
+        self.skip = Some(0);
+
+        let len = match self.trimmed_size() {
+            Some(size) => size.saturating_sub(self.pos).min(buf.len() as u64) as usize,
+            None => buf.len(),
+        };
+        if len == 0 {
+            return 0;
+        }
+        let read = self.inner.read(&mut buf[..len]);
+        if read > 0 {
+            self.pos += read as u64;
+        }
+        read
+    }
+
+    fn buffering_progress(&self) -> Option<BufferingProgress> {
+        self.inner.buffering_progress()
+    }
+
+    fn notifications(&self) -> Option<AudioDecoderNotificationsWrapper> {
+        self.inner.notifications()
+    }
+
+    fn gapless(&mut self) -> Gapless {
+        Gapless::default()
+    }
+}
@@ -0,0 +1,468 @@
+use super::{AudioDecoder, SampleFormat, StreamInfo};
+use std::{convert::TryInto, io};
+
+/// PCM sample stored as little-endian bytes
+pub trait Sample: Copy + Default + PartialEq + Send + 'static {
+    /// `None` if AIMP has no such format
+    const FORMAT: Option<SampleFormat>;
+    const SIZE: usize;
+    /// Significant bits of integer samples, `0` for float ones
+    const BITS: u32;
+
+    fn read_le(bytes: &[u8]) -> Self;
+
+    fn write_le(self, bytes: &mut [u8]);
+
+    /// Value in `-1.0..1.0` range
+    fn to_f64(self) -> f64;
+
+    /// Clamps and rounds integer samples
+    fn from_f64(value: f64) -> Self;
+}
+
+macro_rules! int_sample {
+    ($ty:ty, $format:ident) => {
+        impl Sample for $ty {
+            const FORMAT: Option<SampleFormat> = Some(SampleFormat::$format);
+            const SIZE: usize = std::mem::size_of::<$ty>();
+            const BITS: u32 = Self::SIZE as u32 * 8;
+
+            fn read_le(bytes: &[u8]) -> Self {
+                Self::from_le_bytes(bytes[..Self::SIZE].try_into().unwrap())
+            }
+
+            fn write_le(self, bytes: &mut [u8]) {
+                bytes[..Self::SIZE].copy_from_slice(&self.to_le_bytes());
+            }
+
+            fn to_f64(self) -> f64 {
+                self as f64 / -(<$ty>::MIN as f64)
+            }
+
+            fn from_f64(value: f64) -> Self {
+                let value = (value * -(<$ty>::MIN as f64)).round();
+                value.clamp(<$ty>::MIN as f64, <$ty>::MAX as f64) as $ty
+            }
+        }
+    };
+}
+
+int_sample!(i16, SixteenBit);
+int_sample!(i32, ThirtyTwoBit);
+
+/// 8-bit samples are unsigned with `128` as silence
+impl Sample for u8 {
+    const FORMAT: Option<SampleFormat> = Some(SampleFormat::EightBit);
+    const SIZE: usize = 1;
+    const BITS: u32 = 8;
+
+    fn read_le(bytes: &[u8]) -> Self {
+        bytes[0]
+    }
+
+    fn write_le(self, bytes: &mut [u8]) {
+        bytes[0] = self;
+    }
+
+    fn to_f64(self) -> f64 {
+        (self as f64 - 128.0) / 128.0
+    }
+
+    fn from_f64(value: f64) -> Self {
+        (value * 128.0 + 128.0).round().clamp(0.0, 255.0) as u8
+    }
+}
+
+/// Packed 3-byte sample
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct I24(i32);
+
+impl I24 {
+    pub const MIN: I24 = I24(-0x80_0000);
+    pub const MAX: I24 = I24(0x7f_ffff);
+
+    /// Value is clamped to 24-bit range
+    pub fn new(value: i32) -> Self {
+        Self(value.clamp(Self::MIN.0, Self::MAX.0))
+    }
+
+    pub fn get(self) -> i32 {
+        self.0
+    }
+}
+
+impl Sample for I24 {
+    const FORMAT: Option<SampleFormat> = Some(SampleFormat::TwentyFourBit);
+    const SIZE: usize = 3;
+    const BITS: u32 = 24;
+
+    fn read_le(bytes: &[u8]) -> Self {
+        Self(i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8)
+    }
+
+    fn write_le(self, bytes: &mut [u8]) {
+        bytes[..3].copy_from_slice(&self.0.to_le_bytes()[..3]);
+    }
+
+    fn to_f64(self) -> f64 {
+        self.0 as f64 / 8_388_608.0
+    }
+
+    fn from_f64(value: f64) -> Self {
+        Self::new((value * 8_388_608.0).round() as i32)
+    }
+}
+
+macro_rules! float_sample {
+    ($ty:ty, $format:expr) => {
+        impl Sample for $ty {
+            const FORMAT: Option<SampleFormat> = $format;
+            const SIZE: usize = std::mem::size_of::<$ty>();
+            const BITS: u32 = 0;
+
+            fn read_le(bytes: &[u8]) -> Self {
+                Self::from_le_bytes(bytes[..Self::SIZE].try_into().unwrap())
+            }
+
+            fn write_le(self, bytes: &mut [u8]) {
+                bytes[..Self::SIZE].copy_from_slice(&self.to_le_bytes());
+            }
+
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+
+            fn from_f64(value: f64) -> Self {
+                value as $ty
+            }
+        }
+    };
+}
+
+float_sample!(f32, Some(SampleFormat::ThirtyTwoBitFloat));
+float_sample!(f64, None);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, thiserror::Error)]
+pub enum SampleFormatError {
+    #[error("Decoder has no stream info")]
+    NoStreamInfo,
+    #[error("Unsupported sample format: {0:?}")]
+    Unsupported(SampleFormat),
+}
+
+/// Size of sample in bytes, `None` if format is unsupported
+pub fn sample_size(format: SampleFormat) -> Option<usize> {
+    match format {
+        SampleFormat::EightBit => Some(1),
+        SampleFormat::SixteenBit => Some(2),
+        SampleFormat::TwentyFourBit => Some(3),
+        SampleFormat::ThirtyTwoBit | SampleFormat::ThirtyTwoBitFloat => Some(4),
+        _ => None,
+    }
+}
+
+/// Triangular (TPDF) dither of 1 LSB applied when bit depth is reduced
+#[derive(Debug, Clone)]
+pub struct Dither {
+    seed: u32,
+}
+
+impl Default for Dither {
+    fn default() -> Self {
+        Self::new(0x1234_5678)
+    }
+}
+
+impl Dither {
+    pub fn new(seed: u32) -> Self {
+        Self { seed: seed.max(1) }
+    }
+
+    // xorshift32
+    fn next(&mut self) -> f64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed as f64 / 4_294_967_296.0
+    }
+
+    fn convert<S: Sample, T: Sample>(&mut self, sample: S) -> T {
+        let reduces = T::BITS > 0 && (S::BITS == 0 || T::BITS < S::BITS);
+        if reduces {
+            let lsb = 1.0 / (1u64 << (T::BITS - 1)) as f64;
+            T::from_f64(sample.to_f64() + (self.next() - self.next()) * lsb)
+        } else {
+            T::from_f64(sample.to_f64())
+        }
+    }
+}
+
+/// Converts `min(from.len(), to.len())` samples
+pub fn convert<S: Sample, T: Sample>(from: &[S], to: &mut [T]) {
+    for (from, to) in from.iter().zip(to) {
+        *to = T::from_f64(from.to_f64());
+    }
+}
+
+pub fn convert_with_dither<S: Sample, T: Sample>(from: &[S], to: &mut [T], dither: &mut Dither) {
+    for (from, to) in from.iter().zip(to) {
+        *to = dither.convert(*from);
+    }
+}
+
+fn decode_as<S: Sample, T: Sample>(bytes: &[u8], out: &mut [T], dither: Option<&mut Dither>) {
+    let samples = bytes.chunks_exact(S::SIZE).map(S::read_le);
+    match dither {
+        Some(dither) => samples
+            .zip(out)
+            .for_each(|(sample, out)| *out = dither.convert(sample)),
+        None => samples
+            .zip(out)
+            .for_each(|(sample, out)| *out = T::from_f64(sample.to_f64())),
+    }
+}
+
+pub(crate) type DecodeFn<T> = fn(&[u8], &mut [T], Option<&mut Dither>);
+
+pub(crate) fn decoder_for<T: Sample>(
+    format: SampleFormat,
+) -> Result<DecodeFn<T>, SampleFormatError> {
+    Ok(match format {
+        SampleFormat::EightBit => decode_as::<u8, T>,
+        SampleFormat::SixteenBit => decode_as::<i16, T>,
+        SampleFormat::TwentyFourBit => decode_as::<I24, T>,
+        SampleFormat::ThirtyTwoBit => decode_as::<i32, T>,
+        SampleFormat::ThirtyTwoBitFloat => decode_as::<f32, T>,
+        format => return Err(SampleFormatError::Unsupported(format)),
+    })
+}
+
+/// Converts raw bytes of `format` into typed samples
+pub fn decode<T: Sample>(
+    format: SampleFormat,
+    bytes: &[u8],
+    out: &mut [T],
+    dither: Option<&mut Dither>,
+) -> Result<(), SampleFormatError> {
+    decoder_for(format)?(bytes, out, dither);
+    Ok(())
+}
+
+fn encode_as<S: Sample, T: Sample>(samples: &[S], bytes: &mut [u8], dither: Option<&mut Dither>) {
+    let chunks = bytes.chunks_exact_mut(T::SIZE);
+    match dither {
+        Some(dither) => samples
+            .iter()
+            .zip(chunks)
+            .for_each(|(sample, bytes)| dither.convert::<S, T>(*sample).write_le(bytes)),
+        None => samples
+            .iter()
+            .zip(chunks)
+            .for_each(|(sample, bytes)| T::from_f64(sample.to_f64()).write_le(bytes)),
+    }
+}
+
+pub(crate) type EncodeFn<S> = fn(&[S], &mut [u8], Option<&mut Dither>);
+
+pub(crate) fn encoder_for<S: Sample>(
+    format: SampleFormat,
+) -> Result<EncodeFn<S>, SampleFormatError> {
+    Ok(match format {
+        SampleFormat::EightBit => encode_as::<S, u8>,
+        SampleFormat::SixteenBit => encode_as::<S, i16>,
+        SampleFormat::TwentyFourBit => encode_as::<S, I24>,
+        SampleFormat::ThirtyTwoBit => encode_as::<S, i32>,
+        SampleFormat::ThirtyTwoBitFloat => encode_as::<S, f32>,
+        format => return Err(SampleFormatError::Unsupported(format)),
+    })
+}
+
+/// Converts typed samples into raw bytes of `format`
+pub fn encode<S: Sample>(
+    format: SampleFormat,
+    samples: &[S],
+    bytes: &mut [u8],
+    dither: Option<&mut Dither>,
+) -> Result<(), SampleFormatError> {
+    encoder_for(format)?(samples, bytes, dither);
+    Ok(())
+}
+
+impl StreamInfo {
+    /// Size of one sample of every channel in bytes, `None` if sample format is unsupported
+    pub fn frame_size(&self) -> Option<usize> {
+        Some(sample_size(self.sample_format)? * self.channels as usize)
+    }
+}
+
+const FRAMES_CHUNK: usize = 1024;
+
+/// Reads interleaved samples of decoder converting them to `S`
+pub struct PcmReader<R, S> {
+    decoder: R,
+    info: StreamInfo,
+    frame_size: usize,
+    decode: DecodeFn<S>,
+    dither: Option<Dither>,
+    // partial frame left from previous read
+    pending: Vec<u8>,
+}
+
+impl<R: AudioDecoder, S: Sample> PcmReader<R, S> {
+    pub fn new(mut decoder: R) -> Result<Self, SampleFormatError> {
+        let info = decoder
+            .stream_info()
+            .ok_or(SampleFormatError::NoStreamInfo)?;
+        let decode = decoder_for(info.sample_format)?;
+        let frame_size = info
+            .frame_size()
+            .ok_or(SampleFormatError::Unsupported(info.sample_format))?;
+        Ok(Self {
+            decoder,
+            info,
+            frame_size,
+            decode,
+            dither: None,
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn dither(mut self, dither: Dither) -> Self {
+        self.dither = Some(dither);
+        self
+    }
+
+    pub fn stream_info(&self) -> &StreamInfo {
+        &self.info
+    }
+
+    pub fn channels(&self) -> usize {
+        self.info.channels as usize
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.decoder
+    }
+
+    pub fn into_inner(self) -> R {
+        self.decoder
+    }
+
+    /// Reads whole frames only, returns number of samples which is `0` at the end
+    pub fn read(&mut self, buf: &mut [S]) -> usize {
+        let channels = self.channels().max(1);
+        let frame_size = self.frame_size;
+        let frames = buf.len() / channels;
+        if frames == 0 {
+            return 0;
+        }
+
+        let need = frames * frame_size;
+        let mut filled = self.pending.len();
+        self.pending.resize(need.max(filled), 0);
+        while filled < frame_size {
+            let read = self.decoder.read(&mut self.pending[filled..need]);
+            if read <= 0 {
+                break;
+            }
+            filled += read as usize;
+        }
+
+        let frames = filled / frame_size;
+        let bytes = frames * frame_size;
+        let samples = frames * channels;
+        (self.decode)(
+            &self.pending[..bytes],
+            &mut buf[..samples],
+            self.dither.as_mut(),
+        );
+        self.pending.truncate(filled);
+        self.pending.drain(..bytes);
+        samples
+    }
+
+    /// Iterator over interleaved frames, one sample per channel
+    pub fn frames(&mut self) -> Frames<'_, R, S> {
+        Frames {
+            reader: self,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+pub struct Frames<'a, R, S> {
+    reader: &'a mut PcmReader<R, S>,
+    buf: Vec<S>,
+    pos: usize,
+}
+
+impl<R: AudioDecoder, S: Sample> Iterator for Frames<'_, R, S> {
+    type Item = Vec<S>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let channels = self.reader.channels().max(1);
+        if self.pos == self.buf.len() {
+            self.buf.resize(FRAMES_CHUNK * channels, S::default());
+            let read = self.reader.read(&mut self.buf);
+            self.buf.truncate(read);
+            self.pos = 0;
+        }
+        if self.pos == self.buf.len() {
+            return None;
+        }
+        let frame = self.buf[self.pos..self.pos + channels].to_vec();
+        self.pos += channels;
+        Some(frame)
+    }
+}
+
+/// Writes typed samples as raw bytes of `format`
+///
+/// `&mut [u8]` can be used as writer to fill buffer of `AudioDecoder::read`
+pub struct PcmWriter<W> {
+    inner: W,
+    format: SampleFormat,
+    sample_size: usize,
+    dither: Option<Dither>,
+    scratch: Vec<u8>,
+}
+
+impl<W: io::Write> PcmWriter<W> {
+    pub fn new(inner: W, format: SampleFormat) -> Result<Self, SampleFormatError> {
+        let sample_size = sample_size(format).ok_or(SampleFormatError::Unsupported(format))?;
+        Ok(Self {
+            inner,
+            format,
+            sample_size,
+            dither: None,
+            scratch: Vec::new(),
+        })
+    }
+
+    pub fn dither(mut self, dither: Dither) -> Self {
+        self.dither = Some(dither);
+        self
+    }
+
+    pub fn format(&self) -> SampleFormat {
+        self.format
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    pub fn write<S: Sample>(&mut self, samples: &[S]) -> io::Result<()> {
+        self.scratch.resize(samples.len() * self.sample_size, 0);
+        // format is checked in `new`
+        if let Ok(encode) = encoder_for(self.format) {
+            encode(samples, &mut self.scratch, self.dither.as_mut());
+        }
+        self.inner.write_all(&self.scratch)
+    }
+}
@@ -0,0 +1,147 @@
+use super::{
+    AudioDecoder, AudioDecoderListener, AudioDecoderNotifications,
+    AudioDecoderNotificationsWrapper, BufferingProgress, DecoderChange, Gapless, ListenerSet,
+    StreamInfo,
+};
+use crate::{
+    file::FileInfo,
+    stream::{IcyMetadata, IcyWatch},
+    AimpString,
+};
+
+/// Decoder of internet radio wrapping decoder of audio read from `IcyStream`
+///
+/// Title and artist of file info follow `StreamTitle` of the stream.
+/// AIMP is notified with `DecoderChange::INPUT_FORMAT`, the only change SDK defines,
+/// so it rereads stream and file info
+pub struct RadioDecoder<D> {
+    inner: D,
+    watch: IcyWatch,
+    metadata: Option<IcyMetadata>,
+    listeners: ListenerSet,
+    inner_notifications: Option<AudioDecoderNotificationsWrapper>,
+}
+
+impl<D: AudioDecoder> RadioDecoder<D> {
+    pub fn new(inner: D, watch: IcyWatch) -> Self {
+        let inner_notifications = inner.notifications();
+        Self {
+            inner,
+            watch,
+            metadata: None,
+            listeners: ListenerSet::new(),
+            inner_notifications,
+        }
+    }
+
+    pub fn metadata(&self) -> Option<&IcyMetadata> {
+        self.metadata.as_ref()
+    }
+
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    fn poll_metadata(&mut self) {
+        if let Some(metadata) = self.watch.take() {
+            self.metadata = Some(metadata);
+            self.listeners.notify(DecoderChange::INPUT_FORMAT);
+        }
+    }
+}
+
+impl<D: AudioDecoder> AudioDecoder for RadioDecoder<D> {
+    fn file_info(&mut self) -> Option<FileInfo> {
+        let mut info = self.inner.file_info().unwrap_or_default();
+        if let Some(metadata) = &self.metadata {
+            let (artist, title) = metadata.artist_title();
+            let mut guard = info.update();
+            if let Some(artist) = artist {
+                guard.artist(AimpString::from(artist));
+            }
+            if let Some(title) = title {
+                guard.title(AimpString::from(title));
+            }
+            if let Some(url) = metadata.stream_url() {
+                guard.url(AimpString::from(url));
+            }
+        }
+        Some(info)
+    }
+
+    fn stream_info(&mut self) -> Option<StreamInfo> {
+        self.inner.stream_info()
+    }
+
+    fn is_seekable(&mut self) -> bool {
+        false
+    }
+
+    fn is_realtime_stream(&mut self) -> bool {
+        true
+    }
+
+    fn available_data(&mut self) -> i64 {
+        self.inner.available_data()
+    }
+
+    fn size(&mut self) -> i64 {
+        self.inner.size()
+    }
+
+    fn pos(&mut self) -> i64 {
+        self.inner.pos()
+    }
+
+    fn set_pos(&mut self, _pos: i64) -> bool {
+        false
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> i32 {
+        let read = self.inner.read(buf);
+        self.poll_metadata();
+        read
+    }
+
+    fn buffering_progress(&self) -> Option<BufferingProgress> {
+        self.inner.buffering_progress()
+    }
+
+    fn notifications(&self) -> Option<AudioDecoderNotificationsWrapper> {
+        Some(match &self.inner_notifications {
+            Some(inner) => AudioDecoderNotificationsWrapper::new(Chained(
+                self.listeners.clone(),
+                inner.clone(),
+            )),
+            None => self.listeners.clone().into(),
+        })
+    }
+
+    fn gapless(&mut self) -> Gapless {
+        self.inner.gapless()
+    }
+}
+
+// listeners of AIMP are registered in both radio and wrapped decoder
+struct Chained(ListenerSet, AudioDecoderNotificationsWrapper);
+
+impl AudioDecoderNotifications for Chained {
+    fn add_listener(&self, listener: AudioDecoderListener) {
+        self.0.add_listener(listener.clone());
+        self.1.add_listener(listener);
+    }
+
+    fn remove_listener(&self, listener: AudioDecoderListener) {
+        self.0.remove_listener(listener.clone());
+        self.1.remove_listener(listener);
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+        self.1.flush();
+    }
+}
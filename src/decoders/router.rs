@@ -0,0 +1,230 @@
+use super::{query, AudioDecoderBuilder, AudioDecoderBuilderWrapper, Created};
+use crate::{core::Extension, stream::Stream, AimpString};
+use iaimp::{
+    com_wrapper, ComInterface, ComInterfaceQuerier, ComPtr, ComRc, DecoderFlags, IAIMPAudioDecoder,
+    IAIMPErrorInfo, IAIMPExtensionAudioDecoder, IAIMPExtensionAudioDecoderPriority,
+    IAIMPFileStream, IAIMPServiceAudioDecoders, IAIMPStream, IUnknown, IID,
+};
+use std::{
+    io::{Read, Seek, SeekFrom},
+    mem::MaybeUninit,
+    path::Path,
+};
+use winapi::shared::winerror::{E_FAIL, HRESULT, S_OK};
+
+/// Tells which streams route accepts
+///
+/// Probe matches if any of its magic bytes or file extensions matches,
+/// probe without both matches every stream
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Probe {
+    magic: Vec<(usize, Vec<u8>)>,
+    extensions: Vec<String>,
+}
+
+impl Probe {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn magic<T: Into<Vec<u8>>>(mut self, offset: usize, bytes: T) -> Self {
+        self.magic.push((offset, bytes.into()));
+        self
+    }
+
+    /// Extension without leading dot, compared case-insensitively
+    pub fn extension<T: Into<String>>(mut self, extension: T) -> Self {
+        self.extensions.push(extension.into());
+        self
+    }
+
+    fn header_len(&self) -> usize {
+        self.magic
+            .iter()
+            .map(|(offset, bytes)| offset + bytes.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn matches(&self, header: &[u8], extension: Option<&str>) -> bool {
+        if self.magic.is_empty() && self.extensions.is_empty() {
+            return true;
+        }
+
+        let magic = self
+            .magic
+            .iter()
+            .any(|(offset, bytes)| header.get(*offset..offset + bytes.len()) == Some(&bytes[..]));
+        let extension = match extension {
+            Some(extension) => self
+                .extensions
+                .iter()
+                .any(|ext| ext.eq_ignore_ascii_case(extension)),
+            None => false,
+        };
+        magic || extension
+    }
+}
+
+trait RouteBuilder {
+    unsafe fn try_create(
+        &self,
+        stream: ComRc<dyn IAIMPStream>,
+        flags: DecoderFlags,
+        error_info: ComPtr<dyn IAIMPErrorInfo>,
+        decoder: *mut ComRc<dyn IAIMPAudioDecoder>,
+    ) -> Created;
+}
+
+impl<T: AudioDecoderBuilder> RouteBuilder for AudioDecoderBuilderWrapper<T> {
+    unsafe fn try_create(
+        &self,
+        stream: ComRc<dyn IAIMPStream>,
+        flags: DecoderFlags,
+        error_info: ComPtr<dyn IAIMPErrorInfo>,
+        decoder: *mut ComRc<dyn IAIMPAudioDecoder>,
+    ) -> Created {
+        AudioDecoderBuilderWrapper::try_create(self, stream, flags, error_info, decoder)
+    }
+}
+
+struct Route {
+    probe: Probe,
+    priority: Option<i32>,
+    builder: Box<dyn RouteBuilder>,
+}
+
+/// Decoder extension dispatching streams to several builders
+///
+/// Routes are tried by builder `PRIORITY` in descending order, first bytes of stream are read once
+/// and matched against probes, every route follows `ONLY_INSTANCE` of its own builder.
+/// Next route is tried only if builder reports `DecoderError::is_unsupported`
+#[derive(Default)]
+pub struct DecoderRouter {
+    routes: Vec<Route>,
+}
+
+impl DecoderRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route<T: AudioDecoderBuilder + 'static>(mut self, builder: T, probe: Probe) -> Self {
+        let priority = T::PRIORITY.unwrap_or(0);
+        let idx = self
+            .routes
+            .iter()
+            .position(|route| route.priority.unwrap_or(0) < priority)
+            .unwrap_or(self.routes.len());
+        self.routes.insert(
+            idx,
+            Route {
+                probe,
+                priority: T::PRIORITY,
+                builder: Box::new(AudioDecoderBuilderWrapper::new(builder)),
+            },
+        );
+        self
+    }
+
+    fn priority(&self) -> Option<i32> {
+        self.routes.iter().filter_map(|route| route.priority).max()
+    }
+
+    fn header(&self, stream: &mut Stream) -> Option<Vec<u8>> {
+        let len = self
+            .routes
+            .iter()
+            .map(|route| route.probe.header_len())
+            .max()
+            .unwrap_or(0);
+        let mut header = Vec::with_capacity(len);
+        stream
+            .by_ref()
+            .take(len as u64)
+            .read_to_end(&mut header)
+            .ok()?;
+        Some(header)
+    }
+}
+
+fn file_extension(stream: &Stream) -> Option<String> {
+    let file_stream = query::<dyn IAIMPFileStream, _>(&stream.0)?;
+    let file_name = unsafe {
+        let mut s = MaybeUninit::uninit();
+        if file_stream.get_file_name(s.as_mut_ptr()) != S_OK {
+            return None;
+        }
+        AimpString::from(s.assume_init()).to_string()
+    };
+    Path::new(&file_name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().into_owned())
+}
+
+impl IAIMPExtensionAudioDecoder for DecoderRouter {
+    unsafe fn create_decoder(
+        &self,
+        stream: ComRc<dyn IAIMPStream>,
+        flags: DecoderFlags,
+        error_info: ComPtr<dyn IAIMPErrorInfo>,
+        decoder: *mut ComRc<dyn IAIMPAudioDecoder>,
+    ) -> HRESULT {
+        stream.add_ref();
+        let mut stream = Stream(stream);
+        let start = stream.pos().max(0) as u64;
+        let header = self.header(&mut stream).unwrap_or_default();
+        let extension = file_extension(&stream);
+
+        let routes = self
+            .routes
+            .iter()
+            .filter(|route| route.probe.matches(&header, extension.as_deref()));
+        for route in routes {
+            if stream.seek(SeekFrom::Start(start)).is_err() {
+                return E_FAIL;
+            }
+            // pending or corrupt stream is recognized by builder, so other routes aren't tried
+            match route
+                .builder
+                .try_create(stream.0.clone(), flags, error_info.clone(), decoder)
+            {
+                Created::Unsupported => continue,
+                created => return created.hresult(),
+            }
+        }
+
+        Created::Unsupported.hresult()
+    }
+}
+
+impl IAIMPExtensionAudioDecoderPriority for DecoderRouter {
+    unsafe fn get_priority(&self) -> i32 {
+        self.priority().unwrap_or(0)
+    }
+}
+
+impl Extension for DecoderRouter {
+    const SERVICE_IID: IID = <dyn IAIMPServiceAudioDecoders>::IID;
+}
+
+impl From<DecoderRouter> for ComRc<dyn IAIMPExtensionAudioDecoder> {
+    fn from(router: DecoderRouter) -> Self {
+        let wrapper = com_wrapper!(
+            router =>
+            dyn IAIMPExtensionAudioDecoder,
+            dyn IAIMPExtensionAudioDecoderPriority
+        );
+        unsafe { wrapper.into_com_rc() }
+    }
+}
+
+impl ComInterfaceQuerier for DecoderRouter {
+    fn query_interface(&self, riid: &IID) -> bool {
+        if riid == &<dyn IAIMPExtensionAudioDecoderPriority>::IID {
+            self.priority().is_some()
+        } else {
+            true
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use super::{
+    pcm::{encoder_for, EncodeFn},
+    sample_size, AudioDecoder, AudioDecoderBuilder, AudioDecoderNotificationsWrapper,
+    BufferingProgress, DecoderError, SampleFormat, SampleFormatError, StreamInfo,
+};
+use crate::{
+    file::{
+        FileAttributes, FileClipping, FileInfo, FileInfoCommand, FileInfoGuard, FileInfoProvider,
+        FileSystem, FileUri, StreamingCommand,
+    },
+    stream::{MemoryStream, Stream},
+    AimpString,
+};
+use iaimp::FileStreamingFlags;
+use std::{f64::consts::PI, fmt, io, io::Read, str::FromStr, time::SystemTime};
+
+const SCHEME: &str = "tone://";
+// streams of `tone://` files carry their URI after this header
+const MAGIC: &[u8] = b"AIMP.RS TONE\n";
+const MAX_URI_LEN: u64 = 4096;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ToneError {
+    #[error("Not a tone:// URI")]
+    Scheme,
+    #[error("Unknown waveform `{0}`")]
+    Waveform(String),
+    #[error("Invalid `{0}` parameter")]
+    Param(String),
+    #[error("{0}")]
+    SampleFormat(
+        #[from]
+        #[source]
+        SampleFormatError,
+    ),
+    #[error("{0}")]
+    Io(
+        #[from]
+        #[source]
+        io::Error,
+    ),
+}
+
+impl DecoderError for ToneError {
+    fn is_unsupported(&self) -> bool {
+        matches!(self, ToneError::Scheme)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Waveform {
+    Sine,
+    Square,
+    Noise,
+    Silence,
+}
+
+impl Waveform {
+    fn name(self) -> &'static str {
+        match self {
+            Waveform::Sine => "sine",
+            Waveform::Square => "square",
+            Waveform::Noise => "noise",
+            Waveform::Silence => "silence",
+        }
+    }
+}
+
+fn format_name(format: SampleFormat) -> Option<&'static str> {
+    match format {
+        SampleFormat::EightBit => Some("u8"),
+        SampleFormat::SixteenBit => Some("s16"),
+        SampleFormat::TwentyFourBit => Some("s24"),
+        SampleFormat::ThirtyTwoBit => Some("s32"),
+        SampleFormat::ThirtyTwoBitFloat => Some("f32"),
+        _ => None,
+    }
+}
+
+// splitmix64, so noise depends only on frame and seeking is deterministic
+fn noise(frame: u64) -> f64 {
+    let mut x = frame.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^= x >> 31;
+    (x >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+}
+
+/// Parameters of `tone://waveform?freq=440&secs=5&rate=48000&ch=2&fmt=f32` URI
+///
+/// Omitted parameters take values of `Tone::default()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tone {
+    pub waveform: Waveform,
+    pub freq: f64,
+    pub secs: f64,
+    pub sample_rate: i32,
+    pub channels: i32,
+    pub sample_format: SampleFormat,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Self {
+            waveform: Waveform::Sine,
+            freq: 440.0,
+            secs: 1.0,
+            sample_rate: 44100,
+            channels: 2,
+            sample_format: SampleFormat::SixteenBit,
+        }
+    }
+}
+
+impl Tone {
+    pub fn frames(&self) -> u64 {
+        (self.secs * self.sample_rate as f64).round() as u64
+    }
+
+    pub fn stream_info(&self) -> StreamInfo {
+        StreamInfo {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            sample_format: self.sample_format,
+        }
+    }
+
+    /// Value of frame in `-1.0..=1.0`
+    pub fn sample(&self, frame: u64) -> f64 {
+        let phase = (frame as f64 * self.freq / self.sample_rate as f64).fract();
+        match self.waveform {
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Square if phase < 0.5 => 1.0,
+            Waveform::Square => -1.0,
+            Waveform::Noise => noise(frame),
+            Waveform::Silence => 0.0,
+        }
+    }
+
+    // returns frame size, sizes must fit into `i64` positions and `i32` reads of AIMP
+    fn check(&self) -> Result<usize, ToneError> {
+        let sample_size = sample_size(self.sample_format)
+            .ok_or(SampleFormatError::Unsupported(self.sample_format))?;
+        let frame_size = sample_size as u64 * self.channels.max(0) as u64;
+        let size = (self.secs * self.sample_rate as f64).round() * frame_size as f64;
+        let checks = [
+            ("freq", self.freq.is_finite() && self.freq >= 0.0),
+            (
+                "secs",
+                self.secs.is_finite() && self.secs >= 0.0 && size < i64::MAX as f64,
+            ),
+            ("rate", self.sample_rate > 0),
+            ("ch", self.channels > 0 && frame_size <= i32::MAX as u64),
+        ];
+        match checks.iter().find(|(_, valid)| !valid) {
+            Some((key, _)) => Err(ToneError::Param(key.to_string())),
+            None => Ok(frame_size as usize),
+        }
+    }
+
+    fn stream(&self) -> Result<Stream, ToneError> {
+        let mut data = MAGIC.to_vec();
+        data.extend(self.to_string().as_bytes());
+        Ok(Stream::from(MemoryStream::from_slice(&data)?))
+    }
+}
+
+impl FromStr for Tone {
+    type Err = ToneError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let scheme = s.get(..SCHEME.len()).ok_or(ToneError::Scheme)?;
+        if !scheme.eq_ignore_ascii_case(SCHEME) {
+            return Err(ToneError::Scheme);
+        }
+        let rest = &s[SCHEME.len()..];
+        let (waveform, query) = match rest.find('?') {
+            Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+            None => (rest, ""),
+        };
+
+        let mut tone = Tone {
+            waveform: match waveform.trim_end_matches('/').to_ascii_lowercase().as_str() {
+                "sine" => Waveform::Sine,
+                "square" => Waveform::Square,
+                "noise" => Waveform::Noise,
+                "silence" => Waveform::Silence,
+                waveform => return Err(ToneError::Waveform(waveform.to_string())),
+            },
+            ..Tone::default()
+        };
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = match pair.find('=') {
+                Some(idx) => (&pair[..idx], &pair[idx + 1..]),
+                None => (pair, ""),
+            };
+            let param = || ToneError::Param(key.to_string());
+            match key {
+                "freq" => tone.freq = value.parse().map_err(|_| param())?,
+                "secs" => tone.secs = value.parse().map_err(|_| param())?,
+                "rate" => tone.sample_rate = value.parse().map_err(|_| param())?,
+                "ch" => tone.channels = value.parse().map_err(|_| param())?,
+                "fmt" => {
+                    tone.sample_format = match value {
+                        "u8" => SampleFormat::EightBit,
+                        "s16" => SampleFormat::SixteenBit,
+                        "s24" => SampleFormat::TwentyFourBit,
+                        "s32" => SampleFormat::ThirtyTwoBit,
+                        "f32" => SampleFormat::ThirtyTwoBitFloat,
+                        _ => return Err(param()),
+                    }
+                }
+                _ => return Err(param()),
+            }
+        }
+
+        tone.check()?;
+        Ok(tone)
+    }
+}
+
+impl fmt::Display for Tone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{}?freq={}&secs={}&rate={}&ch={}",
+            SCHEME,
+            self.waveform.name(),
+            self.freq,
+            self.secs,
+            self.sample_rate,
+            self.channels,
+        )?;
+        // unknown format is kept visible, so parsing fails instead of falling back to default
+        match format_name(self.sample_format) {
+            Some(name) => write!(f, "&fmt={}", name),
+            None => write!(f, "&fmt={:?}", self.sample_format),
+        }
+    }
+}
+
+pub struct ToneDecoder {
+    tone: Tone,
+    frame_size: usize,
+    encode: EncodeFn<f64>,
+    frame: u64,
+}
+
+impl ToneDecoder {
+    pub fn new(tone: Tone) -> Result<Self, ToneError> {
+        let frame_size = tone.check()?;
+        Ok(Self {
+            encode: encoder_for(tone.sample_format)?,
+            frame_size,
+            tone,
+            frame: 0,
+        })
+    }
+
+    pub fn tone(&self) -> &Tone {
+        &self.tone
+    }
+}
+
+impl AudioDecoder for ToneDecoder {
+    fn file_info(&mut self) -> Option<FileInfo> {
+        let mut info = FileInfo::default();
+        ToneGenerator::fill(&self.tone, info.update());
+        Some(info)
+    }
+
+    fn stream_info(&mut self) -> Option<StreamInfo> {
+        Some(self.tone.stream_info())
+    }
+
+    fn is_seekable(&mut self) -> bool {
+        true
+    }
+
+    fn is_realtime_stream(&mut self) -> bool {
+        false
+    }
+
+    fn size(&mut self) -> i64 {
+        (self.tone.frames() * self.frame_size as u64) as i64
+    }
+
+    fn pos(&mut self) -> i64 {
+        (self.frame * self.frame_size as u64) as i64
+    }
+
+    fn set_pos(&mut self, pos: i64) -> bool {
+        let frame = pos.max(0) as u64 / self.frame_size as u64;
+        self.frame = frame.min(self.tone.frames());
+        true
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> i32 {
+        let frame_size = self.frame_size;
+        let channels = self.tone.channels as usize;
+        let frames = ((buf.len() / frame_size) as u64).min(self.tone.frames() - self.frame);
+
+        let samples = (self.frame..self.frame + frames)
+            .flat_map(|frame| {
+                let sample = self.tone.sample(frame);
+                (0..channels).map(move |_| sample)
+            })
+            .collect::<Vec<_>>();
+        let len = frames as usize * frame_size;
+        (self.encode)(&samples, &mut buf[..len], None);
+
+        self.frame += frames;
+        len as i32
+    }
+
+    fn buffering_progress(&self) -> Option<BufferingProgress> {
+        None
+    }
+
+    fn notifications(&self) -> Option<AudioDecoderNotificationsWrapper> {
+        None
+    }
+}
+
+/// Generator of test signals addressed by `tone://` URIs
+///
+/// Register `ToneGenerator::file_system()`, `AudioDecoderBuilderWrapper::new(ToneGenerator)`
+/// and optionally `FileInfoProviderWrapper::uri(ToneGenerator)` to play them in AIMP
+pub struct ToneGenerator;
+
+impl ToneGenerator {
+    pub fn file_system() -> FileSystem {
+        let mut file_system = FileSystem::default()
+            .with_file_info(ToneGenerator)
+            .with_streaming(ToneGenerator);
+        file_system
+            .update()
+            .scheme(AimpString::from(SCHEME))
+            .read_only(true);
+        file_system
+    }
+
+    fn fill(tone: &Tone, mut guard: FileInfoGuard) {
+        let bits = match tone.sample_format {
+            SampleFormat::EightBit => 8,
+            SampleFormat::SixteenBit => 16,
+            SampleFormat::TwentyFourBit => 24,
+            _ => 32,
+        };
+        guard
+            .title(AimpString::from(format!(
+                "{} {} Hz",
+                tone.waveform.name(),
+                tone.freq
+            )))
+            .duration(tone.frames() as f64 / tone.sample_rate as f64)
+            .sample_rate(tone.sample_rate)
+            .channels(tone.channels)
+            .bit_depth(bits)
+            .codec(AimpString::from("Tone"))
+            .file_name(AimpString::from(tone.to_string()));
+    }
+}
+
+impl StreamingCommand for ToneGenerator {
+    type Error = ToneError;
+
+    fn create_stream(
+        &self,
+        file_name: AimpString,
+        _flags: FileStreamingFlags,
+        _clipping: FileClipping,
+    ) -> Result<Stream, Self::Error> {
+        file_name.to_string().parse::<Tone>()?.stream()
+    }
+}
+
+impl FileInfoCommand for ToneGenerator {
+    type Error = ToneError;
+
+    fn file_attrs(&self, file_name: AimpString) -> Result<FileAttributes, Self::Error> {
+        file_name.to_string().parse::<Tone>()?;
+        Ok(FileAttributes {
+            created: SystemTime::UNIX_EPOCH,
+            last_accessed: SystemTime::UNIX_EPOCH,
+            last_wrote: SystemTime::UNIX_EPOCH,
+        })
+    }
+
+    fn file_size(&self, file_name: AimpString) -> Result<i64, Self::Error> {
+        Ok(file_name.to_string().parse::<Tone>()?.stream()?.size())
+    }
+
+    fn is_file_exists(&self, file_name: AimpString) -> Result<(), Self::Error> {
+        file_name.to_string().parse::<Tone>().map(drop)
+    }
+}
+
+impl FileInfoProvider for ToneGenerator {
+    type Error = ToneError;
+
+    fn get(&self, file_uri: FileUri, guard: FileInfoGuard) -> Result<(), Self::Error> {
+        let tone = file_uri.to_string().parse::<Tone>()?;
+        Self::fill(&tone, guard);
+        Ok(())
+    }
+}
+
+impl AudioDecoderBuilder for ToneGenerator {
+    const PRIORITY: Option<i32> = None;
+    const ONLY_INSTANCE: bool = false;
+
+    type Decoder = ToneDecoder;
+    type Error = ToneError;
+
+    fn create(&self, stream: Stream) -> Result<Self::Decoder, Self::Error> {
+        let mut magic = Vec::new();
+        let mut stream = stream;
+        (&mut stream)
+            .take(MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+        if magic != MAGIC {
+            return Err(ToneError::Scheme);
+        }
+        let mut uri = Vec::new();
+        stream.take(MAX_URI_LEN).read_to_end(&mut uri)?;
+        ToneDecoder::new(String::from_utf8_lossy(&uri).parse()?)
+    }
+}
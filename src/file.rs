@@ -799,6 +799,18 @@ impl FileUri {
     }
 }
 
+impl From<AimpString> for FileUri {
+    fn from(s: AimpString) -> Self {
+        Self(s)
+    }
+}
+
+impl From<&str> for FileUri {
+    fn from(s: &str) -> Self {
+        Self(AimpString::from(s))
+    }
+}
+
 impl fmt::Debug for FileUri {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         fmt::Debug::fmt(&self.0, f)
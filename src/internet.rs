@@ -1,18 +1,29 @@
+pub use cache::HttpCache;
+pub use download::{Download, DownloadTask};
 pub use iaimp::{ConnectionType, HttpClientPriorityFlags};
+pub use proxy::{ProxyConfig, ProxyCredentials, ProxySubscription};
+pub use session::{Cookie, CookieJar, HttpSession};
+
+mod cache;
+mod download;
+mod proxy;
+mod session;
 
 use crate::{
     error::HresultExt,
     file::FileStream,
+    internet::cache::CacheEntry,
     prop_list,
     prop_list::{PropertyList, PropertyListAccessor},
-    stream::MemoryStream,
+    stream::{MemoryStream, Stream},
     util::Service,
     AimpString, ErrorInfo,
 };
+use futures::StreamExt;
 use http::{
-    header::{ToStrError, CONTENT_LENGTH, CONTENT_TYPE},
+    header::{HeaderName, ToStrError, CONTENT_LENGTH, CONTENT_TYPE, COOKIE},
     uri::InvalidUri,
-    Request, Uri,
+    HeaderMap, HeaderValue, Request, StatusCode, Uri,
 };
 use iaimp::{
     com_wrapper, ComInterfaceQuerier, ComPtr, ComRc, ConnectionSettingsProp, ConnectionTypeWrapper,
@@ -20,15 +31,19 @@ use iaimp::{
     IAIMPHTTPClientEvents2, IAIMPPropertyList, IAIMPServiceConnectionSettings,
     IAIMPServiceHTTPClient2, IAIMPStream, IAIMPString,
 };
+use parking_lot::Mutex;
 use std::{
     convert::TryFrom,
     io, mem,
     mem::MaybeUninit,
     os::raw::c_void,
+    path::PathBuf,
+    pin::Pin,
     sync::{
         mpsc,
-        mpsc::{Receiver, Sender, SyncSender},
+        mpsc::{Receiver, SyncSender},
     },
+    task::{Context, Poll},
 };
 use winapi::shared::minwindef::{BOOL, TRUE};
 
@@ -103,6 +118,14 @@ pub enum HttpError {
     Failed(ErrorInfo),
     #[error("Method is not supported")]
     UnsupportedMethod,
+    #[error("Unexpected status: {0}")]
+    Status(StatusCode),
+    #[error("{0}")]
+    Aimp(
+        #[from]
+        #[source]
+        crate::Error,
+    ),
 }
 
 pub struct HttpClient(ComPtr<dyn IAIMPServiceHTTPClient2>);
@@ -118,6 +141,15 @@ impl HttpClient {
     {
         Ok(Request::get(uri).body(())?.into())
     }
+
+    /// Downloads into `<path>.part` and renames it to `path` when done
+    pub fn download<T, P>(uri: T, path: P) -> Result<Download>
+    where
+        Uri: TryFrom<T, Error = InvalidUri>,
+        P: Into<PathBuf>,
+    {
+        Ok(Download::new(Self::get(uri)?, path.into()))
+    }
 }
 
 impl From<ComPtr<dyn IAIMPServiceHTTPClient2>> for HttpClient {
@@ -151,6 +183,10 @@ impl Body for FileStream {
 pub struct RequestBuilder<T> {
     request: Request<Option<T>>,
     priority: HttpClientPriorityFlags,
+    session: Option<HttpSession>,
+    cache: Option<HttpCache>,
+    on_progress: Option<ProgressCallback>,
+    progress_offset: u64,
 }
 
 impl<T> RequestBuilder<T>
@@ -162,14 +198,50 @@ where
         self
     }
 
+    pub fn session(mut self, session: &HttpSession) -> Self {
+        self.session = Some(session.clone());
+        self
+    }
+
+    /// Only `GET` requests are cached, fresh responses are returned
+    /// without network access by `send_and_wait`
+    pub fn cache(mut self, cache: &HttpCache) -> Self {
+        self.cache = Some(cache.clone());
+        self
+    }
+
+    pub fn on_progress<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(u64, Option<u64>) + Send + 'static,
+    {
+        self.on_progress = Some(Box::new(f));
+        self
+    }
+
+    fn cache_lookup(&self) -> Option<CacheEntry> {
+        match self.request.method() {
+            &http::Method::GET => self.cache.as_ref()?.lookup(self.request.uri()),
+            _ => None,
+        }
+    }
+
     fn make_uri_and_headers(&self) -> Result<AimpString> {
         let uri = self.request.uri().to_string();
-        let headers = self
-            .request
-            .headers()
-            .iter()
-            .map(|(k, v)| Ok(format!("\r\n{}: {}", k, v.to_str()?)))
-            .collect::<Result<String>>()?;
+        let mut headers = String::new();
+        let mut cookies = Vec::new();
+        for (k, v) in self.request.headers() {
+            if self.session.is_some() && k == COOKIE {
+                cookies.push(v.to_str()?.to_string());
+            } else {
+                headers += &format!("\r\n{}: {}", k, v.to_str()?);
+            }
+        }
+        if let Some(session) = &self.session {
+            cookies.extend(session.cookie_header(self.request.uri()));
+            if !cookies.is_empty() {
+                headers += &format!("\r\n{}: {}", COOKIE, cookies.join("; "));
+            }
+        }
         Ok(AimpString::from(uri + &headers))
     }
 
@@ -184,11 +256,23 @@ where
         }
     }
 
-    fn inner_send(mut self, flags: HttpClientRestFlags) -> Result<HttpTask> {
+    fn inner_send<A: AsRef<Stream>>(
+        mut self,
+        flags: HttpClientRestFlags,
+        answer_data: A,
+        cached: Option<CacheEntry>,
+    ) -> Result<HttpTask<A>> {
+        if let Some(entry) = &cached {
+            entry.add_validators(self.request.headers_mut());
+        }
+        let cache = match self.request.method() {
+            &http::Method::GET => self.cache.take().map(|cache| (cache, cached)),
+            _ => None,
+        };
+
         let uri_and_headers = self.make_uri_and_headers()?.0;
         let method = self.match_method()?;
         let flags = HttpClientFlags::new(HttpClientRestFlags::UTF8 | flags, self.priority);
-        let answer_data = MemoryStream::default();
         let post_data = self
             .request
             .body_mut()
@@ -197,12 +281,14 @@ where
             .into_stream()
             .transpose()?;
 
-        let downloaded = mpsc::channel();
+        let progress = futures::channel::mpsc::unbounded();
         let status = mpsc::sync_channel(1);
         let content_info = mpsc::sync_channel(1);
         let complete = mpsc::sync_channel(1);
         let events_handler = EventsHandler {
-            downloaded: downloaded.0,
+            progress: progress.0,
+            on_progress: self.on_progress.take().map(Mutex::new),
+            progress_offset: self.progress_offset,
             status: status.0,
             content_info: content_info.0,
             complete: complete.0,
@@ -219,7 +305,7 @@ where
                     uri_and_headers,
                     method,
                     flags,
-                    (*answer_data).0.as_raw().cast(),
+                    answer_data.as_ref().0.as_raw().cast(),
                     post_data,
                     events_handler.into_com_rc(),
                     None,
@@ -230,8 +316,11 @@ where
 
             Ok(HttpTask {
                 id: task_id.assume_init(),
+                uri: self.request.uri().clone(),
+                session: self.session,
+                cache,
                 answer_data,
-                downloaded: downloaded.1,
+                progress: Some(Progress(progress.1)),
                 status: status.1,
                 content_info: content_info.1,
                 complete: complete.1,
@@ -240,11 +329,25 @@ where
     }
 
     pub fn send(self) -> Result<HttpTask> {
-        self.inner_send(HttpClientRestFlags::NONE)
+        let cached = self.cache_lookup();
+        self.inner_send(HttpClientRestFlags::NONE, MemoryStream::default(), cached)
     }
 
     pub fn send_and_wait(self) -> Result<http::Response<MemoryStream>> {
-        self.inner_send(HttpClientRestFlags::WAIT_FOR)?.wait()
+        let mut cached = self.cache_lookup();
+        if let (Some(cache), Some(entry)) = (&self.cache, &mut cached) {
+            if entry.is_fresh() {
+                if let Ok(response) = cache.hit(entry) {
+                    return Ok(response);
+                }
+            }
+        }
+        self.inner_send(
+            HttpClientRestFlags::WAIT_FOR,
+            MemoryStream::default(),
+            cached,
+        )?
+        .wait()
     }
 }
 
@@ -254,20 +357,32 @@ impl<T> From<Request<T>> for RequestBuilder<T> {
         Self {
             request: Request::from_parts(parts, Some(body)),
             priority: Default::default(),
+            session: None,
+            cache: None,
+            on_progress: None,
+            progress_offset: 0,
         }
     }
 }
 
-pub struct HttpTask {
+pub struct HttpTask<T = MemoryStream> {
     id: *const c_void,
-    answer_data: MemoryStream,
-    pub downloaded: Receiver<u32>,
+    uri: Uri,
+    session: Option<HttpSession>,
+    cache: Option<(HttpCache, Option<CacheEntry>)>,
+    answer_data: T,
+    progress: Option<Progress>,
     status: Receiver<AimpString>,
-    content_info: Receiver<(AimpString, u32)>,
+    content_info: Receiver<(AimpString, i64)>,
     complete: Receiver<(Option<ErrorInfo>, BOOL)>,
 }
 
-impl HttpTask {
+impl<T> HttpTask<T> {
+    /// Can be taken only once, next calls return `None`
+    pub fn progress(&mut self) -> Option<Progress> {
+        self.progress.take()
+    }
+
     fn inner_cancel(self, rest: HttpClientRestFlags) {
         unsafe {
             HTTP_CLIENT
@@ -290,7 +405,7 @@ impl HttpTask {
         self.inner_cancel(HttpClientRestFlags::WAIT_FOR)
     }
 
-    pub fn wait(self) -> Result<http::Response<MemoryStream>> {
+    fn inner_wait(self) -> Result<http::Response<T>> {
         let (info, canceled) = self.complete.recv().unwrap();
         match (info, canceled == TRUE) {
             (_, true) => Err(HttpError::Canceled),
@@ -298,14 +413,28 @@ impl HttpTask {
             (None, false) => {
                 let mut builder = http::Response::builder();
 
-                let status_line = self.status.recv().unwrap().to_string();
+                let header = self.status.recv().unwrap().to_string();
+                let mut lines = header.lines();
+                let status_line = lines.next().unwrap_or_default();
                 let status = status_line.split_ascii_whitespace().nth(1).unwrap();
                 builder = builder.status(status);
 
+                let headers = builder.headers_mut().unwrap();
+                *headers = parse_headers(lines);
+
                 let (content_type, content_length) = self.content_info.recv().unwrap();
-                builder = builder
-                    .header(CONTENT_TYPE, content_type.to_string())
-                    .header(CONTENT_LENGTH, content_length);
+                if !headers.contains_key(CONTENT_TYPE) {
+                    if let Ok(content_type) = HeaderValue::from_str(&content_type.to_string()) {
+                        headers.insert(CONTENT_TYPE, content_type);
+                    }
+                }
+                if !headers.contains_key(CONTENT_LENGTH) && content_length >= 0 {
+                    headers.insert(CONTENT_LENGTH, content_length.into());
+                }
+
+                if let Some(session) = &self.session {
+                    session.store(&self.uri, headers);
+                }
 
                 Ok(builder.body(self.answer_data)?)
             }
@@ -313,10 +442,66 @@ impl HttpTask {
     }
 }
 
+impl HttpTask {
+    pub fn wait(mut self) -> Result<http::Response<MemoryStream>> {
+        let cache = self.cache.take();
+        let uri = self.uri.clone();
+        let response = self.inner_wait()?;
+        match cache {
+            Some((cache, Some(entry))) if response.status() == StatusCode::NOT_MODIFIED => {
+                Ok(cache.revalidated(entry, response.headers())?)
+            }
+            Some((cache, _)) => {
+                // failed cache write must not fail the request
+                let _ = cache.store(&uri, &response);
+                Ok(response)
+            }
+            None => Ok(response),
+        }
+    }
+}
+
+fn parse_headers<'a, I: Iterator<Item = &'a str>>(lines: I) -> HeaderMap {
+    lines
+        .filter_map(|line| {
+            let mut line = line.splitn(2, ':');
+            let name = HeaderName::from_bytes(line.next()?.trim().as_bytes()).ok()?;
+            let value = HeaderValue::from_str(line.next()?.trim()).ok()?;
+            Some((name, value))
+        })
+        .fold(HeaderMap::new(), |mut map, (name, value)| {
+            map.append(name, value);
+            map
+        })
+}
+
+type ProgressCallback = Box<dyn FnMut(u64, Option<u64>) + Send>;
+
+/// `(downloaded, total)` updates, `total` is `None` if server didn't report size
+pub struct Progress(futures::channel::mpsc::UnboundedReceiver<(u64, Option<u64>)>);
+
+impl Iterator for Progress {
+    type Item = (u64, Option<u64>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        futures::executor::block_on(self.0.next())
+    }
+}
+
+impl futures::Stream for Progress {
+    type Item = (u64, Option<u64>);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx)
+    }
+}
+
 struct EventsHandler {
-    downloaded: Sender<u32>,
+    progress: futures::channel::mpsc::UnboundedSender<(u64, Option<u64>)>,
+    on_progress: Option<Mutex<ProgressCallback>>,
+    progress_offset: u64,
     status: SyncSender<AimpString>,
-    content_info: SyncSender<(AimpString, u32)>,
+    content_info: SyncSender<(AimpString, i64)>,
     complete: SyncSender<(Option<ErrorInfo>, BOOL)>,
 }
 
@@ -329,7 +514,7 @@ impl IAIMPHTTPClientEvents for EventsHandler {
     ) {
         *allow = TRUE;
         self.content_info
-            .send((AimpString(content_type), content_size as u32))
+            .send((AimpString(content_type), content_size))
             .unwrap();
     }
 
@@ -339,8 +524,18 @@ impl IAIMPHTTPClientEvents for EventsHandler {
             .unwrap();
     }
 
-    unsafe fn on_progress(&self, downloaded: i64, _total: i64) {
-        self.downloaded.send(downloaded as u32).unwrap();
+    unsafe fn on_progress(&self, downloaded: i64, total: i64) {
+        let downloaded = self.progress_offset + downloaded.max(0) as u64;
+        let total = if total > 0 {
+            Some(self.progress_offset + total as u64)
+        } else {
+            None
+        };
+        if let Some(f) = &self.on_progress {
+            (f.lock())(downloaded, total);
+        }
+        // receiver is allowed to be dropped
+        let _ = self.progress.unbounded_send((downloaded, total));
     }
 }
 
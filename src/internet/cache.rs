@@ -0,0 +1,335 @@
+use crate::{stream::MemoryStream, CorePath, CORE};
+use http::{
+    header::{
+        HeaderName, HeaderValue, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, ETAG, EXPIRES,
+        IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, SET_COOKIE, VARY,
+    },
+    HeaderMap, Response, StatusCode, Uri,
+};
+use parking_lot::Mutex;
+use std::{
+    fs, io,
+    io::{Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const DEFAULT_MAX_SIZE: u64 = 64 * 1024 * 1024;
+const DEFAULT_MAX_ENTRIES: usize = 1024;
+const HEURISTIC_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn to_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn from_secs(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn body_size(path: &Path) -> u64 {
+    fs::metadata(path).map_or(0, |meta| meta.len())
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// FNV-1a, stable between runs unlike `DefaultHasher`
+fn key(uri: &str) -> String {
+    let hash = uri.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+enum Freshness {
+    NoStore,
+    Until(SystemTime),
+}
+
+fn freshness(headers: &HeaderMap, now: SystemTime) -> Freshness {
+    let cache_control = headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    if headers.contains_key(VARY) || cache_control.iter().any(|d| d == "no-store") {
+        return Freshness::NoStore;
+    }
+    if cache_control.iter().any(|d| d == "no-cache") {
+        return Freshness::Until(now);
+    }
+
+    let max_age = cache_control
+        .iter()
+        .filter_map(|d| d.strip_prefix("max-age="))
+        .find_map(|secs| secs.trim_matches('"').parse().ok());
+    if let Some(secs) = max_age {
+        return Freshness::Until(now + Duration::from_secs(secs));
+    }
+
+    let date = |name| {
+        headers
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+            .and_then(|value| httpdate::parse_http_date(value).ok())
+    };
+    if headers.contains_key(EXPIRES) {
+        // invalid dates like "0" mean "already expired"
+        return Freshness::Until(date(EXPIRES).unwrap_or(UNIX_EPOCH));
+    }
+    match date(LAST_MODIFIED).and_then(|modified| now.duration_since(modified).ok()) {
+        Some(age) => Freshness::Until(now + (age / 10).min(HEURISTIC_MAX_AGE)),
+        None => Freshness::Until(now),
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct CacheEntry {
+    uri: String,
+    status: StatusCode,
+    last_used: SystemTime,
+    fresh_until: SystemTime,
+    headers: HeaderMap,
+    path: PathBuf,
+}
+
+impl CacheEntry {
+    pub(crate) fn is_fresh(&self) -> bool {
+        SystemTime::now() < self.fresh_until
+    }
+
+    pub(crate) fn add_validators(&self, headers: &mut HeaderMap) {
+        if let Some(etag) = self.headers.get(ETAG) {
+            headers.insert(IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(modified) = self.headers.get(LAST_MODIFIED) {
+            headers.insert(IF_MODIFIED_SINCE, modified.clone());
+        }
+    }
+
+    fn body_path(&self) -> PathBuf {
+        self.path.with_extension("body")
+    }
+
+    fn read(path: PathBuf) -> io::Result<Self> {
+        let meta = fs::read_to_string(&path)?;
+        let mut lines = meta.lines();
+        let mut next = || {
+            lines
+                .next()
+                .ok_or_else(|| invalid_data("truncated cache entry"))
+        };
+        let uri = next()?.to_string();
+        let status = StatusCode::from_bytes(next()?.as_bytes())
+            .map_err(|_| invalid_data("invalid cached status"))?;
+        let last_used = from_secs(next()?.parse().map_err(|_| invalid_data("invalid time"))?);
+        let fresh_until = from_secs(next()?.parse().map_err(|_| invalid_data("invalid time"))?);
+        let headers = lines
+            .filter_map(|line| {
+                let mut line = line.splitn(2, ':');
+                let name = HeaderName::from_bytes(line.next()?.trim().as_bytes()).ok()?;
+                let value = HeaderValue::from_str(line.next()?.trim()).ok()?;
+                Some((name, value))
+            })
+            .fold(HeaderMap::new(), |mut map, (name, value)| {
+                map.append(name, value);
+                map
+            });
+        Ok(Self {
+            uri,
+            status,
+            last_used,
+            fresh_until,
+            headers,
+            path,
+        })
+    }
+
+    fn write(&self) -> io::Result<()> {
+        let mut meta = format!(
+            "{}\n{}\n{}\n{}\n",
+            self.uri,
+            self.status.as_str(),
+            to_secs(self.last_used),
+            to_secs(self.fresh_until)
+        );
+        for (name, value) in &self.headers {
+            if let Ok(value) = value.to_str() {
+                meta += &format!("{}: {}\n", name, value);
+            }
+        }
+        fs::write(&self.path, meta)
+    }
+
+    fn response(&self) -> io::Result<Response<MemoryStream>> {
+        let data = fs::read(self.body_path())?;
+        let mut body = MemoryStream::default();
+        body.write_all(&data)?;
+        body.seek(SeekFrom::Start(0))?;
+
+        let mut response = Response::new(body);
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        Ok(response)
+    }
+}
+
+struct CacheInner {
+    dir: PathBuf,
+    max_size: u64,
+    max_entries: usize,
+}
+
+#[derive(Clone)]
+pub struct HttpCache(Arc<Mutex<CacheInner>>);
+
+impl HttpCache {
+    /// Cache stored in `HttpCache` folder of AIMP profile
+    pub fn new() -> io::Result<Self> {
+        let profile = CORE.get().path(CorePath::Profile).to_string();
+        Self::with_dir(Path::new(&profile).join("HttpCache"))
+    }
+
+    pub fn with_dir<P: Into<PathBuf>>(dir: P) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self(Arc::new(Mutex::new(CacheInner {
+            dir,
+            max_size: DEFAULT_MAX_SIZE,
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }))))
+    }
+
+    pub fn max_size(self, bytes: u64) -> Self {
+        self.0.lock().max_size = bytes;
+        self
+    }
+
+    pub fn max_entries(self, entries: usize) -> Self {
+        self.0.lock().max_entries = entries;
+        self
+    }
+
+    pub fn clear(&self) -> io::Result<()> {
+        let inner = self.0.lock();
+        for entry in fs::read_dir(&inner.dir)? {
+            fs::remove_file(entry?.path())?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn lookup(&self, uri: &Uri) -> Option<CacheEntry> {
+        let inner = self.0.lock();
+        let uri = uri.to_string();
+        let path = inner.dir.join(key(&uri)).with_extension("meta");
+        CacheEntry::read(path).ok().filter(|entry| entry.uri == uri)
+    }
+
+    /// Serves fresh entry and marks it as used
+    pub(crate) fn hit(&self, entry: &mut CacheEntry) -> io::Result<Response<MemoryStream>> {
+        let _inner = self.0.lock();
+        let response = entry.response()?;
+        entry.last_used = SystemTime::now();
+        // stale `last_used` only affects eviction order
+        let _ = entry.write();
+        Ok(response)
+    }
+
+    pub(crate) fn revalidated(
+        &self,
+        mut entry: CacheEntry,
+        headers: &HeaderMap,
+    ) -> io::Result<Response<MemoryStream>> {
+        let _inner = self.0.lock();
+        for (name, value) in headers {
+            // these describe the empty 304 answer, not the cached body
+            if name != CONTENT_TYPE && name != CONTENT_LENGTH {
+                entry.headers.insert(name, value.clone());
+            }
+        }
+        let now = SystemTime::now();
+        match freshness(&entry.headers, now) {
+            Freshness::Until(fresh_until) => {
+                entry.fresh_until = fresh_until;
+                entry.last_used = now;
+                entry.write()?;
+                entry.response()
+            }
+            Freshness::NoStore => {
+                let (meta_path, body_path) = (entry.path.clone(), entry.body_path());
+                let response = entry.response()?;
+                fs::remove_file(meta_path)?;
+                fs::remove_file(body_path)?;
+                Ok(response)
+            }
+        }
+    }
+
+    pub(crate) fn store(&self, uri: &Uri, response: &Response<MemoryStream>) -> io::Result<()> {
+        let inner = self.0.lock();
+        if response.status() != StatusCode::OK {
+            return Ok(());
+        }
+        let now = SystemTime::now();
+        let fresh_until = match freshness(response.headers(), now) {
+            Freshness::Until(fresh_until) => fresh_until,
+            Freshness::NoStore => return Ok(()),
+        };
+        let has_validators =
+            response.headers().contains_key(ETAG) || response.headers().contains_key(LAST_MODIFIED);
+        let body = response.body().data();
+        if (fresh_until <= now && !has_validators) || body.len() as u64 > inner.max_size {
+            return Ok(());
+        }
+
+        let uri = uri.to_string();
+        let mut entry = CacheEntry {
+            path: inner.dir.join(key(&uri)).with_extension("meta"),
+            uri,
+            status: response.status(),
+            last_used: now,
+            fresh_until,
+            headers: response.headers().clone(),
+        };
+        entry.headers.remove(SET_COOKIE);
+        fs::write(entry.body_path(), &*body)?;
+        entry.write()?;
+
+        inner.evict(&entry.path)
+    }
+}
+
+impl CacheInner {
+    fn evict(&self, keep: &Path) -> io::Result<()> {
+        let mut entries = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension() == Some("meta".as_ref()) && path != keep)
+            .filter_map(|path| CacheEntry::read(path).ok())
+            .map(|entry| {
+                let size = body_size(&entry.body_path());
+                (entry, size)
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(entry, _)| entry.last_used);
+
+        let mut total = body_size(&keep.with_extension("body"))
+            + entries.iter().map(|(_, size)| size).sum::<u64>();
+        let mut count = entries.len() + 1;
+        for (entry, size) in entries {
+            if total <= self.max_size && count <= self.max_entries {
+                break;
+            }
+            fs::remove_file(entry.body_path())?;
+            fs::remove_file(&entry.path)?;
+            total -= size;
+            count -= 1;
+        }
+        Ok(())
+    }
+}
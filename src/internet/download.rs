@@ -0,0 +1,176 @@
+use super::{
+    HttpClientPriorityFlags, HttpClientRestFlags, HttpError, HttpSession, HttpTask, Progress,
+    RequestBuilder, Result,
+};
+use crate::file::FileStream;
+use http::{
+    header::{CONTENT_RANGE, RANGE},
+    HeaderValue, Response, StatusCode,
+};
+use std::{
+    ffi::OsString,
+    fs, io,
+    io::{Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+pub struct Download {
+    request: RequestBuilder<()>,
+    path: PathBuf,
+}
+
+impl Download {
+    pub(super) fn new(request: RequestBuilder<()>, path: PathBuf) -> Self {
+        Self { request, path }
+    }
+
+    pub fn priority(mut self, priority: HttpClientPriorityFlags) -> Self {
+        self.request = self.request.priority(priority);
+        self
+    }
+
+    pub fn session(mut self, session: &HttpSession) -> Self {
+        self.request = self.request.session(session);
+        self
+    }
+
+    /// Progress includes bytes downloaded by previous attempts
+    pub fn on_progress<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(u64, Option<u64>) + Send + 'static,
+    {
+        self.request = self.request.on_progress(f);
+        self
+    }
+
+    fn part_path(&self) -> PathBuf {
+        let mut part = OsString::from(self.path.as_os_str());
+        part.push(".part");
+        PathBuf::from(part)
+    }
+
+    fn inner_send(mut self, flags: HttpClientRestFlags) -> Result<DownloadTask> {
+        let part = self.part_path();
+        let offset = fs::metadata(&part).map_or(0, |meta| meta.len());
+        let file_name = part.to_string_lossy().into_owned();
+
+        let file = if offset > 0 {
+            let range = HeaderValue::from_str(&format!("bytes={}-", offset)).unwrap();
+            self.request.request.headers_mut().insert(RANGE, range);
+            self.request.progress_offset = offset;
+
+            let mut file = FileStream::options().read_write(true).open(file_name)?;
+            file.seek(SeekFrom::End(0))?;
+            file
+        } else {
+            FileStream::options().create_new(true).open(file_name)?
+        };
+
+        let cached = self.request.cache_lookup();
+        Ok(DownloadTask {
+            task: self.request.inner_send(flags, file, cached)?,
+            part,
+            path: self.path,
+            offset,
+        })
+    }
+
+    pub fn send(self) -> Result<DownloadTask> {
+        self.inner_send(HttpClientRestFlags::NONE)
+    }
+
+    pub fn send_and_wait(self) -> Result<Response<PathBuf>> {
+        self.inner_send(HttpClientRestFlags::WAIT_FOR)?.wait()
+    }
+}
+
+/// Interrupted download keeps `.part` file and resumes from it next time
+pub struct DownloadTask {
+    task: HttpTask<FileStream>,
+    part: PathBuf,
+    path: PathBuf,
+    offset: u64,
+}
+
+impl DownloadTask {
+    pub fn progress(&mut self) -> Option<Progress> {
+        self.task.progress()
+    }
+
+    pub fn cancel(self) {
+        self.task.cancel()
+    }
+
+    pub fn cancel_and_wait(self) {
+        self.task.cancel_and_wait()
+    }
+
+    pub fn wait(self) -> Result<Response<PathBuf>> {
+        let offset = self.offset;
+        let (parts, mut file) = self.task.inner_wait()?.into_parts();
+        let content_range = parts
+            .headers
+            .get(CONTENT_RANGE)
+            .and_then(|range| range.to_str().ok())
+            .and_then(|range| range.strip_prefix("bytes "));
+
+        match parts.status {
+            StatusCode::PARTIAL_CONTENT => {
+                let start = content_range
+                    .and_then(|range| range.split('-').next())
+                    .and_then(|start| start.trim().parse::<u64>().ok());
+                if start != Some(offset) {
+                    file.set_size(offset as i64)?;
+                    return Err(HttpError::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Content-Range doesn't match downloaded part",
+                    )));
+                }
+            }
+            // server ignored `Range` and sent whole file after the part
+            StatusCode::OK if offset > 0 => discard_prefix(&mut file, offset)?,
+            StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => {
+                let len = content_range
+                    .and_then(|range| range.strip_prefix("*/"))
+                    .and_then(|len| len.trim().parse::<u64>().ok());
+                if len == Some(offset) {
+                    // part is already complete
+                    file.set_size(offset as i64)?;
+                } else {
+                    // part doesn't belong to this file, next attempt starts from scratch
+                    drop(file);
+                    fs::remove_file(&self.part)?;
+                    return Err(HttpError::Status(parts.status));
+                }
+            }
+            status if !status.is_success() => {
+                file.set_size(offset as i64)?;
+                return Err(HttpError::Status(status));
+            }
+            _ => {}
+        }
+
+        drop(file);
+        fs::rename(&self.part, &self.path)?;
+        Ok(Response::from_parts(parts, self.path))
+    }
+}
+
+fn discard_prefix(file: &mut FileStream, offset: u64) -> Result<()> {
+    let mut buf = vec![0; 64 * 1024];
+    let mut read_pos = offset;
+    let mut write_pos = 0;
+    loop {
+        file.seek(SeekFrom::Start(read_pos))?;
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        file.seek(SeekFrom::Start(write_pos))?;
+        file.write_all(&buf[..read])?;
+        read_pos += read as u64;
+        write_pos += read as u64;
+    }
+    file.set_size(write_pos as i64)?;
+    Ok(())
+}
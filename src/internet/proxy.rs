@@ -0,0 +1,178 @@
+use super::{ConnectionSettings, ConnectionSettingsGuard, ConnectionType, CONNECTION_SETTINGS};
+use crate::{
+    messages::{MessageHook, MESSAGE_DISPATCHER},
+    AimpString,
+};
+use http::{uri::InvalidUri, Uri};
+use iaimp::{ConnectionSettingsProp, AIMP_MSG_EVENT_OPTIONS};
+use std::{cell::RefCell, convert::TryFrom, fmt::Write};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyConfig {
+    pub scheme: String,
+    pub host: String,
+    pub port: Option<u16>,
+    pub credentials: Option<ProxyCredentials>,
+}
+
+impl ProxyConfig {
+    pub fn to_uri(&self) -> Result<Uri, InvalidUri> {
+        let mut uri = format!("{}://", self.scheme);
+        if let Some(credentials) = &self.credentials {
+            uri += &percent_encode(&credentials.username);
+            if !credentials.password.is_empty() {
+                uri.push(':');
+                uri += &percent_encode(&credentials.password);
+            }
+            uri.push('@');
+        }
+        uri += &self.host_and_port();
+        Uri::try_from(uri)
+    }
+
+    fn bracketed_host(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        }
+    }
+
+    fn host_and_port(&self) -> String {
+        let mut s = self.bracketed_host();
+        if let Some(port) = self.port {
+            write!(s, ":{}", port).unwrap();
+        }
+        s
+    }
+
+    pub(crate) fn parse(server: &str, port: &str) -> Option<Self> {
+        let server = server.trim().trim_end_matches('/');
+        let (scheme, server) = match server.find("://") {
+            Some(idx) => (server[..idx].to_ascii_lowercase(), &server[idx + 3..]),
+            None => ("http".to_string(), server),
+        };
+
+        let (host, server_port) = if server.starts_with('[') {
+            let end = server.find(']')?;
+            let port = server[end + 1..]
+                .strip_prefix(':')
+                .and_then(|p| p.parse().ok());
+            (&server[1..end], port)
+        } else {
+            match server.rfind(':') {
+                Some(idx) if server[..idx].find(':').is_none() => {
+                    (&server[..idx], server[idx + 1..].parse().ok())
+                }
+                _ => (server, None),
+            }
+        };
+        if host.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            scheme,
+            host: host.to_string(),
+            port: port.trim().parse().ok().or(server_port),
+            credentials: None,
+        })
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes().fold(String::new(), |mut encoded, byte| {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => write!(encoded, "%{:02X}", byte).unwrap(),
+        }
+        encoded
+    })
+}
+
+impl ConnectionSettings {
+    fn string(&self, prop: ConnectionSettingsProp) -> String {
+        self.prop_list
+            .get::<Option<AimpString>>(prop as i32)
+            .map(|s| s.to_string())
+            .unwrap_or_default()
+    }
+
+    /// `None` if AIMP connects directly or uses system settings
+    pub fn proxy(&self) -> Option<ProxyConfig> {
+        if self.connection_type() != Some(ConnectionType::Proxy) {
+            return None;
+        }
+
+        let mut proxy = ProxyConfig::parse(
+            &self.string(ConnectionSettingsProp::ProxyServer),
+            &self.string(ConnectionSettingsProp::ProxyPort),
+        )?;
+        let username = self.string(ConnectionSettingsProp::ProxyUsername);
+        if !username.is_empty() {
+            proxy.credentials = Some(ProxyCredentials {
+                username,
+                password: self.string(ConnectionSettingsProp::ProxyUserPass),
+            });
+        }
+        Some(proxy)
+    }
+
+    /// Called with new proxy configuration every time user changes it
+    pub fn on_proxy_change<F>(mut f: F) -> ProxySubscription
+    where
+        F: FnMut(Option<ProxyConfig>) + 'static,
+    {
+        let last = RefCell::new(CONNECTION_SETTINGS.get().proxy());
+        let hook = MESSAGE_DISPATCHER.get().hook(move |message, _, _| {
+            if message == AIMP_MSG_EVENT_OPTIONS {
+                let proxy = CONNECTION_SETTINGS.get().proxy();
+                if *last.borrow() != proxy {
+                    last.replace(proxy.clone());
+                    f(proxy);
+                }
+            }
+        });
+        ProxySubscription { _hook: hook }
+    }
+}
+
+impl ConnectionSettingsGuard<'_> {
+    pub fn proxy(&mut self, proxy: Option<&ProxyConfig>) -> &mut Self {
+        let proxy = match proxy {
+            Some(proxy) => proxy,
+            None => return self.connection_type(Some(ConnectionType::Direct)),
+        };
+
+        let server = if proxy.scheme.eq_ignore_ascii_case("http") {
+            proxy.bracketed_host()
+        } else {
+            format!("{}://{}", proxy.scheme, proxy.bracketed_host())
+        };
+        let port = proxy.port.map(|port| port.to_string()).unwrap_or_default();
+        let (username, password) = proxy
+            .credentials
+            .as_ref()
+            .map(|c| (c.username.as_str(), c.password.as_str()))
+            .unwrap_or_default();
+
+        self.connection_type(Some(ConnectionType::Proxy))
+            .proxy_server(server.into())
+            .proxy_port(port.into())
+            .proxy_username(username.into())
+            .proxy_user_pass(password.into())
+    }
+}
+
+/// Unsubscribes on drop
+pub struct ProxySubscription {
+    _hook: MessageHook,
+}
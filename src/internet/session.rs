@@ -0,0 +1,350 @@
+use super::{Body, HttpClient, RequestBuilder, Result};
+use crate::{config::CONFIG, AimpString};
+use http::{header::SET_COOKIE, uri::InvalidUri, HeaderMap, Request, Uri};
+use parking_lot::Mutex;
+use std::{
+    cmp::Reverse,
+    convert::TryFrom,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub domain: String,
+    pub host_only: bool,
+    pub path: String,
+    pub expires: Option<SystemTime>,
+    pub secure: bool,
+    pub http_only: bool,
+}
+
+impl Cookie {
+    pub fn parse(set_cookie: &str, uri: &Uri) -> Option<Self> {
+        let host = uri.host()?.to_ascii_lowercase();
+        let mut attrs = set_cookie.split(';');
+
+        let mut pair = attrs.next()?.splitn(2, '=');
+        let name = pair.next()?.trim();
+        let value = pair.next()?.trim();
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(uri.path()),
+            expires: None,
+            secure: false,
+            http_only: false,
+        };
+        let mut max_age = None;
+
+        for attr in attrs {
+            let mut attr = attr.splitn(2, '=');
+            let key = attr.next().unwrap().trim();
+            let value = attr.next().map(str::trim).unwrap_or_default();
+
+            if key.eq_ignore_ascii_case("expires") {
+                if let Some(expires) = parse_cookie_date(value) {
+                    cookie.expires = Some(expires);
+                }
+            } else if key.eq_ignore_ascii_case("max-age") {
+                if let Ok(secs) = value.parse::<i64>() {
+                    max_age = Some(if secs <= 0 {
+                        UNIX_EPOCH
+                    } else {
+                        SystemTime::now() + Duration::from_secs(secs as u64)
+                    });
+                }
+            } else if key.eq_ignore_ascii_case("domain") {
+                let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                if !domain.is_empty() {
+                    if !domain_match(&host, &domain) {
+                        return None;
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+            } else if key.eq_ignore_ascii_case("path") {
+                if value.starts_with('/') {
+                    cookie.path = value.to_string();
+                }
+            } else if key.eq_ignore_ascii_case("secure") {
+                cookie.secure = true;
+            } else if key.eq_ignore_ascii_case("httponly") {
+                cookie.http_only = true;
+            }
+        }
+
+        // only secure origins can set `Secure` cookies
+        if cookie.secure && uri.scheme_str() != Some("https") {
+            return None;
+        }
+        if max_age.is_some() {
+            cookie.expires = max_age;
+        }
+
+        Some(cookie)
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.expires.is_some()
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        matches!(self.expires, Some(expires) if expires <= now)
+    }
+
+    pub fn matches(&self, uri: &Uri) -> bool {
+        let host = match uri.host() {
+            Some(host) => host.to_ascii_lowercase(),
+            None => return false,
+        };
+        let domain_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(&host, &self.domain)
+        };
+        let secure_ok = !self.secure || uri.scheme_str() == Some("https");
+        domain_ok && secure_ok && path_match(uri.path(), &self.path)
+    }
+
+    // Netscape `cookies.txt` line
+    fn to_line(&self) -> String {
+        let expires = self
+            .expires
+            .and_then(|expires| expires.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |expires| expires.as_secs());
+        format!(
+            "{}{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            if self.http_only { "#HttpOnly_" } else { "" },
+            self.domain,
+            if self.host_only { "FALSE" } else { "TRUE" },
+            self.path,
+            if self.secure { "TRUE" } else { "FALSE" },
+            expires,
+            self.name,
+            self.value
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let (http_only, line) = match line.strip_prefix("#HttpOnly_") {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let mut fields = line.split('\t');
+        let domain = fields.next()?.to_string();
+        let host_only = fields.next()? == "FALSE";
+        let path = fields.next()?.to_string();
+        let secure = fields.next()? == "TRUE";
+        let expires = fields.next()?.parse::<u64>().ok()?;
+        let name = fields.next()?.to_string();
+        let value = fields.next()?.to_string();
+        Some(Self {
+            name,
+            value,
+            domain,
+            host_only,
+            path,
+            expires: Some(UNIX_EPOCH + Duration::from_secs(expires)),
+            secure,
+            http_only,
+        })
+    }
+}
+
+fn parse_cookie_date(s: &str) -> Option<SystemTime> {
+    httpdate::parse_http_date(s)
+        .or_else(|_| httpdate::parse_http_date(&s.replace('-', " ")))
+        .ok()
+}
+
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(idx) if idx > 0 && path.starts_with('/') => path[..idx].to_string(),
+        _ => "/".to_string(),
+    }
+}
+
+fn domain_match(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    host.parse::<IpAddr>().is_err()
+        && host.ends_with(domain)
+        && host[..host.len() - domain.len()].ends_with('.')
+}
+
+fn path_match(path: &str, cookie_path: &str) -> bool {
+    let path = if path.is_empty() { "/" } else { path };
+    path == cookie_path
+        || (path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/')))
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+
+impl CookieJar {
+    pub fn insert(&mut self, cookie: Cookie) {
+        self.insert_at(cookie, SystemTime::now())
+    }
+
+    fn insert_at(&mut self, cookie: Cookie, now: SystemTime) {
+        self.cookies.retain(|c| {
+            !(c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path)
+        });
+        if !cookie.is_expired(now) {
+            self.cookies.push(cookie);
+        }
+    }
+
+    pub fn remove_expired(&mut self) {
+        let now = SystemTime::now();
+        self.cookies.retain(|cookie| !cookie.is_expired(now));
+    }
+
+    pub fn clear(&mut self) {
+        self.cookies.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cookie> {
+        self.cookies.iter()
+    }
+
+    pub fn header_value(&self, uri: &Uri) -> Option<String> {
+        let now = SystemTime::now();
+        let mut cookies = self
+            .cookies
+            .iter()
+            .filter(|cookie| !cookie.is_expired(now) && cookie.matches(uri))
+            .collect::<Vec<_>>();
+        if cookies.is_empty() {
+            return None;
+        }
+        // longer paths first, as RFC 6265 recommends
+        cookies.sort_by_key(|cookie| Reverse(cookie.path.len()));
+        let header = cookies
+            .into_iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        Some(header)
+    }
+
+    fn to_lines(&self) -> String {
+        self.cookies
+            .iter()
+            .filter(|cookie| cookie.is_persistent())
+            .map(Cookie::to_line)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn from_lines(lines: &str) -> Self {
+        let mut jar = Self::default();
+        let now = SystemTime::now();
+        lines
+            .lines()
+            .filter_map(Cookie::from_line)
+            .for_each(|cookie| jar.insert_at(cookie, now));
+        jar
+    }
+}
+
+#[derive(Debug, Default)]
+struct SessionInner {
+    jar: Mutex<CookieJar>,
+    key_path: Option<String>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct HttpSession(Arc<SessionInner>);
+
+impl HttpSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load cookies from AIMP config and save them back on every change
+    pub fn persistent<T: Into<String>>(key_path: T) -> Self {
+        let key_path = key_path.into();
+        let jar = CONFIG
+            .get()
+            .get::<AimpString, _>(key_path.as_str())
+            .map(|lines| CookieJar::from_lines(&lines.to_string()))
+            .unwrap_or_default();
+        Self(Arc::new(SessionInner {
+            jar: Mutex::new(jar),
+            key_path: Some(key_path),
+        }))
+    }
+
+    pub fn request<T: Body>(&self, req: Request<T>) -> RequestBuilder<T> {
+        HttpClient::request(req).session(self)
+    }
+
+    pub fn get<T>(&self, uri: T) -> Result<RequestBuilder<()>>
+    where
+        Uri: TryFrom<T, Error = InvalidUri>,
+    {
+        Ok(HttpClient::get(uri)?.session(self))
+    }
+
+    pub fn cookies(&self) -> CookieJar {
+        self.0.jar.lock().clone()
+    }
+
+    /// Fails if persistent session can't save cookies, they are kept in memory then
+    pub fn insert(&self, cookie: Cookie) -> Result<()> {
+        self.0.jar.lock().insert(cookie);
+        self.save()
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        self.0.jar.lock().clear();
+        self.save()
+    }
+
+    pub(crate) fn cookie_header(&self, uri: &Uri) -> Option<String> {
+        self.0.jar.lock().header_value(uri)
+    }
+
+    pub(crate) fn store(&self, uri: &Uri, headers: &HeaderMap) {
+        let mut changed = false;
+        {
+            let mut jar = self.0.jar.lock();
+            for value in headers.get_all(SET_COOKIE) {
+                if let Some(cookie) = value.to_str().ok().and_then(|s| Cookie::parse(s, uri)) {
+                    jar.insert(cookie);
+                    changed = true;
+                }
+            }
+        }
+        // failed config write must not fail the request
+        if changed {
+            let _ = self.save();
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(key_path) = &self.0.key_path {
+            let mut jar = self.0.jar.lock();
+            jar.remove_expired();
+            CONFIG
+                .get()
+                .set(key_path.as_str(), AimpString::from(jar.to_lines()))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{error::HresultExt, util::Service};
+use iaimp::{
+    com_wrapper, ComInterfaceQuerier, ComPtr, ComRc, IAIMPMessageHook,
+    IAIMPServiceMessageDispatcher,
+};
+use std::{cell::RefCell, os::raw::c_void};
+use winapi::shared::{minwindef::DWORD, winerror::HRESULT};
+
+pub(crate) static MESSAGE_DISPATCHER: Service<MessageDispatcher> = Service::new();
+
+pub(crate) struct MessageDispatcher(ComPtr<dyn IAIMPServiceMessageDispatcher>);
+
+impl MessageDispatcher {
+    pub fn hook<F>(&self, f: F) -> MessageHook
+    where
+        F: FnMut(DWORD, i32, *mut c_void) + 'static,
+    {
+        unsafe {
+            let hook = com_wrapper!(MessageHookWrapper(RefCell::new(f)) => dyn IAIMPMessageHook)
+                .into_com_rc();
+            self.0.hook(hook.as_raw()).into_result().unwrap();
+            MessageHook(hook)
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn send(&self, message: DWORD) {
+        unsafe {
+            self.0
+                .send(message, 0, std::ptr::null_mut())
+                .into_result()
+                .unwrap();
+        }
+    }
+}
+
+impl From<ComPtr<dyn IAIMPServiceMessageDispatcher>> for MessageDispatcher {
+    fn from(ptr: ComPtr<dyn IAIMPServiceMessageDispatcher>) -> Self {
+        Self(ptr)
+    }
+}
+
+pub(crate) struct MessageHook(ComRc<dyn IAIMPMessageHook>);
+
+impl Drop for MessageHook {
+    fn drop(&mut self) {
+        if MESSAGE_DISPATCHER.is_available() {
+            unsafe {
+                MESSAGE_DISPATCHER
+                    .get()
+                    .0
+                    .unhook(self.0.as_raw())
+                    .into_result()
+                    .unwrap();
+            }
+        }
+    }
+}
+
+struct MessageHookWrapper<F>(RefCell<F>);
+
+impl<F> IAIMPMessageHook for MessageHookWrapper<F>
+where
+    F: FnMut(DWORD, i32, *mut c_void),
+{
+    unsafe fn core_message(
+        &self,
+        message: DWORD,
+        param1: i32,
+        param2: *mut c_void,
+        _result: *mut HRESULT,
+    ) {
+        (self.0.borrow_mut())(message, param1, param2)
+    }
+}
+
+impl<F> ComInterfaceQuerier for MessageHookWrapper<F> {}
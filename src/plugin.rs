@@ -1,5 +1,6 @@
 use crate::{
     actions::ACTION_MANAGER_SERVICE,
+    config::CONFIG,
     core::CORE,
     decoders::AUDIO_DECODERS,
     file::{
@@ -7,17 +8,19 @@ use crate::{
         FILE_STREAMING, FILE_SYSTEMS, FILE_URI_SERVICE,
     },
     internet::{CONNECTION_SETTINGS, HTTP_CLIENT},
+    messages::MESSAGE_DISPATCHER,
     msg_box,
     threading::THREADS,
     util::ToWide,
 };
 use iaimp::{
     ComInterface, ComInterfaceQuerier, ComPtr, IAIMPCore, IAIMPPlugin, IAIMPServiceActionManager,
-    IAIMPServiceAudioDecoders, IAIMPServiceConnectionSettings, IAIMPServiceFileFormats,
-    IAIMPServiceFileInfo, IAIMPServiceFileInfoFormatter, IAIMPServiceFileInfoFormatterUtils,
-    IAIMPServiceFileStreaming, IAIMPServiceFileSystems, IAIMPServiceFileURI2,
-    IAIMPServiceHTTPClient2, IAIMPServiceThreads, IUnknown, PluginCategory, PluginInfoWrapper,
-    SystemNotification, SystemNotificationWrapper,
+    IAIMPServiceAudioDecoders, IAIMPServiceConfig, IAIMPServiceConnectionSettings,
+    IAIMPServiceFileFormats, IAIMPServiceFileInfo, IAIMPServiceFileInfoFormatter,
+    IAIMPServiceFileInfoFormatterUtils, IAIMPServiceFileStreaming, IAIMPServiceFileSystems,
+    IAIMPServiceFileURI2, IAIMPServiceHTTPClient2, IAIMPServiceMessageDispatcher,
+    IAIMPServiceThreads, IUnknown, PluginCategory, PluginInfoWrapper, SystemNotification,
+    SystemNotificationWrapper,
 };
 use std::{
     cell::Cell, error::Error as StdError, mem::MaybeUninit, ptr, result::Result as StdResult,
@@ -84,8 +87,10 @@ impl<T: Plugin> IAIMPPlugin for PluginWrapper<T> {
         CORE.init(core);
         let core = CORE.get();
         THREADS.init(core.query_object());
+        CONFIG.init(core.query_object());
         CONNECTION_SETTINGS.init(core.query_object());
         HTTP_CLIENT.init(core.query_object());
+        MESSAGE_DISPATCHER.init(core.query_object());
         ACTION_MANAGER_SERVICE.init(core.query_object());
 
         FILE_FORMATS.init(core.query_object());
@@ -152,8 +157,10 @@ impl<T: Plugin> IAIMPPlugin for PluginWrapper<T> {
 
         match_service!(
             THREADS: IAIMPServiceThreads,
+            CONFIG: IAIMPServiceConfig,
             CONNECTION_SETTINGS: IAIMPServiceConnectionSettings,
             HTTP_CLIENT: IAIMPServiceHTTPClient2,
+            MESSAGE_DISPATCHER: IAIMPServiceMessageDispatcher,
             ACTION_MANAGER_SERVICE: IAIMPServiceActionManager,
             FILE_FORMATS: IAIMPServiceFileFormats,
             FILE_INFO_SERVICE: IAIMPServiceFileInfo,
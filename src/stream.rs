@@ -1,14 +1,45 @@
-use crate::{core::CORE, error::HresultExt, Error, ErrorKind, Result};
-use futures::io::SeekFrom;
-use iaimp::{ComInterface, ComPtr, ComRc, IAIMPMemoryStream, IAIMPStream, StreamSeekFrom};
+pub use buffered::BufferedStream;
+pub use digest::{Digest, DigestAlgorithm, DigestTask};
+pub use icy::{IcyMetadata, IcyStream, IcyWatch};
+
+mod buffered;
+mod digest;
+mod icy;
+
+use crate::{
+    core::CORE,
+    error::HresultExt,
+    threading::{TaskHandle, THREADS},
+    Error, ErrorKind, Result,
+};
+use futures::{
+    channel::oneshot,
+    io::{AsyncRead, AsyncSeek, AsyncWrite, SeekFrom},
+    ready, Future,
+};
+use iaimp::{
+    com_wrapper, ComInterface, ComInterfaceQuerier, ComPtr, ComRc, IAIMPMemoryStream, IAIMPStream,
+    StreamSeekFrom, HRESULT,
+};
+use parking_lot::{Mutex, ReentrantMutex};
 use std::{
+    cell::RefCell,
+    convert::TryFrom,
     fmt, io,
     io::{Read, Seek, Write},
     mem::MaybeUninit,
-    ops::{Deref, DerefMut},
+    ops::{Bound, Deref, DerefMut, RangeBounds},
+    os::raw::{c_int, c_uchar},
+    pin::Pin,
     slice,
+    task::{Context, Poll},
+};
+use winapi::shared::{
+    minwindef::DWORD,
+    winerror::{
+        E_ACCESSDENIED, E_FAIL, E_INVALIDARG, E_NOTIMPL, E_OUTOFMEMORY, HRESULT_FROM_WIN32, S_OK,
+    },
 };
-use winapi::shared::winerror::E_FAIL;
 
 #[derive(Debug, thiserror::Error)]
 pub enum StreamError {
@@ -18,6 +49,10 @@ pub enum StreamError {
 
 pub struct Stream(pub(crate) ComRc<dyn IAIMPStream>);
 
+// memory streams with borrowed data, slices are not allowed to write into them
+static BORROWED_DATA: ReentrantMutex<RefCell<Vec<usize>>> =
+    parking_lot::const_reentrant_mutex(RefCell::new(Vec::new()));
+
 impl Stream {
     pub(crate) unsafe fn as_inner<T: ComInterface + IAIMPStream + ?Sized>(&self) -> ComPtr<T> {
         self.0.as_raw().cast()
@@ -34,6 +69,37 @@ impl Stream {
     pub fn pos(&self) -> i64 {
         unsafe { self.0.get_position() }
     }
+
+    pub fn into_async(self) -> AsyncStream {
+        AsyncStream::new(self)
+    }
+
+    pub fn from_rust<T: Read + Write + Seek + Send + 'static>(inner: T) -> Self {
+        RustStream::wrap(inner, Some(|inner: &mut T, buf: &[u8]| inner.write(buf)))
+    }
+
+    /// Writing to such stream fails with `E_NOTIMPL`
+    pub fn from_rust_reader<T: Read + Seek + Send + 'static>(inner: T) -> Self {
+        RustStream::wrap(inner, None)
+    }
+
+    /// Stream over byte range of this stream
+    ///
+    /// Slice has its own position, reading or writing it moves position of parent.
+    /// Range without end stops at current size, so slice never resizes parent
+    pub fn slice<R: RangeBounds<u64>>(&self, range: R) -> Stream {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.saturating_add(1),
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.size().max(0) as u64,
+        };
+        SliceStream::wrap(self, start, end)
+    }
 }
 
 impl Seek for Stream {
@@ -88,6 +154,424 @@ impl Write for Stream {
     }
 }
 
+struct SendStream(Stream);
+
+// AIMP streams are not bound to thread they were created in
+unsafe impl Send for SendStream {}
+
+enum AsyncOp {
+    Read(io::Result<Vec<u8>>),
+    Write(io::Result<usize>),
+    Seek(io::Result<u64>),
+}
+
+enum AsyncState {
+    Idle(SendStream),
+    Busy(oneshot::Receiver<(SendStream, AsyncOp)>, TaskHandle),
+}
+
+/// Runs blocking stream calls on `THREADS`
+///
+/// Result of operation which future was dropped is returned to the next call
+pub struct AsyncStream {
+    state: Option<AsyncState>,
+    buf: Vec<u8>,
+    buf_pos: usize,
+}
+
+impl AsyncStream {
+    pub fn new<T: Into<Stream>>(stream: T) -> Self {
+        Self {
+            state: Some(AsyncState::Idle(SendStream(stream.into()))),
+            buf: Vec::new(),
+            buf_pos: 0,
+        }
+    }
+
+    pub fn into_inner(mut self) -> io::Result<Stream> {
+        match self.state.take().unwrap() {
+            AsyncState::Idle(stream) => Ok(stream.0),
+            AsyncState::Busy(rx, handle) => {
+                handle.wait();
+                futures::executor::block_on(rx)
+                    .map(|(stream, _)| stream.0)
+                    .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+            }
+        }
+    }
+
+    fn spawn<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut Stream) -> AsyncOp + Send + 'static,
+    {
+        let mut stream = match self.state.take() {
+            Some(AsyncState::Idle(stream)) => stream,
+            _ => unreachable!(),
+        };
+        let (tx, rx) = oneshot::channel();
+        let handle = THREADS.get().spawn(async move {
+            let op = f(&mut stream.0);
+            let _ = tx.send((stream, op));
+        });
+        self.state = Some(AsyncState::Busy(rx, handle));
+    }
+
+    fn poll_op(&mut self, cx: &mut Context) -> Poll<io::Result<Option<AsyncOp>>> {
+        let rx = match &mut self.state {
+            Some(AsyncState::Busy(rx, _)) => rx,
+            Some(AsyncState::Idle(_)) => return Poll::Ready(Ok(None)),
+            None => {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "stream was lost by canceled task",
+                )))
+            }
+        };
+        match Pin::new(rx).poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(res) => {
+                self.state = None;
+                let (stream, op) = res.map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+                self.state = Some(AsyncState::Idle(stream));
+                Poll::Ready(Ok(Some(op)))
+            }
+        }
+    }
+
+    fn buffered(&self) -> usize {
+        self.buf.len() - self.buf_pos
+    }
+
+    fn keep_read(&mut self, res: io::Result<Vec<u8>>) -> io::Result<()> {
+        self.buf = res?;
+        self.buf_pos = 0;
+        Ok(())
+    }
+}
+
+impl AsyncRead for AsyncStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            match ready!(self.poll_op(cx))? {
+                None if self.buffered() > 0 => {
+                    let len = self.buffered().min(buf.len());
+                    let pos = self.buf_pos;
+                    buf[..len].copy_from_slice(&self.buf[pos..pos + len]);
+                    self.buf_pos += len;
+                    return Poll::Ready(Ok(len));
+                }
+                None => {
+                    let len = buf.len();
+                    self.spawn(move |stream| {
+                        let mut data = vec![0; len];
+                        AsyncOp::Read(stream.read(&mut data).map(|read| {
+                            data.truncate(read);
+                            data
+                        }))
+                    });
+                }
+                Some(AsyncOp::Read(res)) => {
+                    self.keep_read(res)?;
+                    if self.buffered() == 0 {
+                        return Poll::Ready(Ok(0));
+                    }
+                }
+                Some(AsyncOp::Write(res)) => drop(res?),
+                Some(AsyncOp::Seek(res)) => drop(res?),
+            }
+        }
+    }
+}
+
+impl AsyncWrite for AsyncStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            match ready!(self.poll_op(cx))? {
+                // move position back to where reader stopped
+                None if self.buffered() > 0 => {
+                    let pos = SeekFrom::Current(-(self.buffered() as i64));
+                    self.buf.clear();
+                    self.buf_pos = 0;
+                    self.spawn(move |stream| AsyncOp::Seek(stream.seek(pos)));
+                }
+                None => {
+                    let data = buf.to_vec();
+                    self.spawn(move |stream| AsyncOp::Write(stream.write(&data)));
+                }
+                Some(AsyncOp::Write(res)) => return Poll::Ready(res),
+                Some(AsyncOp::Read(res)) => self.keep_read(res)?,
+                Some(AsyncOp::Seek(res)) => drop(res?),
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match ready!(self.poll_op(cx))? {
+            Some(AsyncOp::Read(res)) => self.keep_read(res)?,
+            Some(AsyncOp::Write(res)) => drop(res?),
+            Some(AsyncOp::Seek(res)) => drop(res?),
+            None => {}
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl AsyncSeek for AsyncStream {
+    fn poll_seek(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        pos: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        loop {
+            match ready!(self.poll_op(cx))? {
+                None => {
+                    let pos = match pos {
+                        SeekFrom::Current(offset) => {
+                            SeekFrom::Current(offset - self.buffered() as i64)
+                        }
+                        pos => pos,
+                    };
+                    self.buf.clear();
+                    self.buf_pos = 0;
+                    self.spawn(move |stream| AsyncOp::Seek(stream.seek(pos)));
+                }
+                Some(AsyncOp::Seek(res)) => return Poll::Ready(res),
+                Some(AsyncOp::Read(res)) => self.keep_read(res)?,
+                Some(AsyncOp::Write(res)) => drop(res?),
+            }
+        }
+    }
+}
+
+type WriteFn<T> = fn(&mut T, &[u8]) -> io::Result<usize>;
+
+struct RustStream<T> {
+    inner: Mutex<T>,
+    write: Option<WriteFn<T>>,
+}
+
+impl<T: Read + Seek + Send + 'static> RustStream<T> {
+    fn wrap(inner: T, write: Option<WriteFn<T>>) -> Stream {
+        let wrapper = Self {
+            inner: Mutex::new(inner),
+            write,
+        };
+        unsafe { Stream(com_wrapper!(wrapper => dyn IAIMPStream).into_com_rc()) }
+    }
+}
+
+impl<T: Read + Seek> RustStream<T> {
+    fn size(&self) -> io::Result<u64> {
+        let mut inner = self.inner.lock();
+        let pos = inner.seek(SeekFrom::Current(0))?;
+        let size = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(pos))?;
+        Ok(size)
+    }
+}
+
+fn io_error_to_hresult(err: io::Error) -> HRESULT {
+    let res = match (err.raw_os_error(), err.kind()) {
+        (Some(code), _) => HRESULT_FROM_WIN32(code as u32),
+        (None, io::ErrorKind::PermissionDenied) => E_ACCESSDENIED,
+        (None, io::ErrorKind::InvalidInput) => E_INVALIDARG,
+        (None, io::ErrorKind::OutOfMemory) => E_OUTOFMEMORY,
+        (None, _) => E_FAIL,
+    };
+    HRESULT(res)
+}
+
+impl<T: Read + Seek> IAIMPStream for RustStream<T> {
+    unsafe fn get_size(&self) -> i64 {
+        self.size().map_or(-1, |size| size as i64)
+    }
+
+    unsafe fn set_size(&self, _value: i64) -> HRESULT {
+        HRESULT(E_NOTIMPL)
+    }
+
+    unsafe fn get_position(&self) -> i64 {
+        self.inner
+            .lock()
+            .seek(SeekFrom::Current(0))
+            .map_or(-1, |pos| pos as i64)
+    }
+
+    unsafe fn seek(&self, offset: i64, mode: StreamSeekFrom) -> HRESULT {
+        let pos = match mode {
+            StreamSeekFrom::Beginning if offset < 0 => return HRESULT(E_FAIL),
+            StreamSeekFrom::Beginning => SeekFrom::Start(offset as u64),
+            StreamSeekFrom::Current => SeekFrom::Current(offset),
+            StreamSeekFrom::End => SeekFrom::End(offset),
+        };
+        // `Stream` treats `E_FAIL` as invalid offset
+        match self.inner.lock().seek(pos) {
+            Ok(_) => HRESULT(S_OK),
+            Err(_) => HRESULT(E_FAIL),
+        }
+    }
+
+    unsafe fn read(&self, buffer: *mut c_uchar, count: DWORD) -> c_int {
+        let buf = slice::from_raw_parts_mut(buffer, count as usize);
+        let mut inner = self.inner.lock();
+        loop {
+            match inner.read(buf) {
+                Ok(read) => break read as c_int,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break -1,
+            }
+        }
+    }
+
+    unsafe fn write(&self, buffer: *const c_uchar, count: DWORD, written: *mut DWORD) -> HRESULT {
+        let write = match self.write {
+            Some(write) => write,
+            None => return HRESULT(E_NOTIMPL),
+        };
+        let buf = slice::from_raw_parts(buffer, count as usize);
+        let mut inner = self.inner.lock();
+        loop {
+            match write(&mut inner, buf) {
+                Ok(count) => {
+                    if !written.is_null() {
+                        *written = count as DWORD;
+                    }
+                    break HRESULT(S_OK);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => break io_error_to_hresult(err),
+            }
+        }
+    }
+}
+
+impl<T> ComInterfaceQuerier for RustStream<T> {}
+
+struct SliceStream {
+    parent: Mutex<Stream>,
+    start: u64,
+    end: u64,
+    pos: Mutex<u64>,
+}
+
+impl SliceStream {
+    fn wrap(parent: &Stream, start: u64, end: u64) -> Stream {
+        let wrapper = Self {
+            parent: Mutex::new(Stream(parent.0.clone())),
+            start,
+            end: end.max(start),
+            pos: Mutex::new(0),
+        };
+        unsafe { Stream(com_wrapper!(wrapper => dyn IAIMPStream).into_com_rc()) }
+    }
+
+    // parent may shrink after slice was created
+    fn size(&self, parent: &Stream) -> u64 {
+        let parent_size = parent.size().max(0) as u64;
+        self.end.min(parent_size).saturating_sub(self.start)
+    }
+
+    fn remaining(&self, parent: &Stream) -> usize {
+        let remaining = self.size(parent).saturating_sub(*self.pos.lock());
+        remaining.min(usize::MAX as u64) as usize
+    }
+
+    fn seek_parent(&self, parent: &mut Stream) -> io::Result<u64> {
+        parent.seek(SeekFrom::Start(self.start + *self.pos.lock()))
+    }
+
+    fn is_borrowed(parent: &Stream, borrowed: &[usize]) -> bool {
+        borrowed.contains(&(parent.0.as_raw().as_ptr() as usize))
+    }
+}
+
+impl IAIMPStream for SliceStream {
+    unsafe fn get_size(&self) -> i64 {
+        self.size(&self.parent.lock()) as i64
+    }
+
+    unsafe fn set_size(&self, _value: i64) -> HRESULT {
+        HRESULT(E_NOTIMPL)
+    }
+
+    unsafe fn get_position(&self) -> i64 {
+        *self.pos.lock() as i64
+    }
+
+    unsafe fn seek(&self, offset: i64, mode: StreamSeekFrom) -> HRESULT {
+        let base = match mode {
+            StreamSeekFrom::Beginning => 0,
+            StreamSeekFrom::Current => *self.pos.lock() as i64,
+            StreamSeekFrom::End => self.size(&self.parent.lock()) as i64,
+        };
+        // `Stream` treats `E_FAIL` as invalid offset
+        match base.checked_add(offset) {
+            Some(pos) if pos >= 0 => {
+                *self.pos.lock() = pos as u64;
+                HRESULT(S_OK)
+            }
+            _ => HRESULT(E_FAIL),
+        }
+    }
+
+    unsafe fn read(&self, buffer: *mut c_uchar, count: DWORD) -> c_int {
+        let mut parent = self.parent.lock();
+        let len = self.remaining(&parent).min(count as usize);
+        if len == 0 {
+            return 0;
+        }
+        if self.seek_parent(&mut parent).is_err() {
+            return -1;
+        }
+        let buf = slice::from_raw_parts_mut(buffer, len);
+        match parent.read(buf) {
+            Ok(read) => {
+                *self.pos.lock() += read as u64;
+                read as c_int
+            }
+            Err(_) => -1,
+        }
+    }
+
+    unsafe fn write(&self, buffer: *const c_uchar, count: DWORD, written: *mut DWORD) -> HRESULT {
+        // lock is held until write is done, so data can't be borrowed in the middle of it
+        let borrowed = BORROWED_DATA.lock();
+        let mut parent = self.parent.lock();
+        if Self::is_borrowed(&parent, &borrowed.borrow()) {
+            return HRESULT(E_ACCESSDENIED);
+        }
+        let len = self.remaining(&parent).min(count as usize);
+        if let Err(err) = self.seek_parent(&mut parent) {
+            return io_error_to_hresult(err);
+        }
+        let buf = slice::from_raw_parts(buffer, len);
+        match parent.write(buf) {
+            Ok(count) => {
+                *self.pos.lock() += count as u64;
+                if !written.is_null() {
+                    *written = count as DWORD;
+                }
+                HRESULT(S_OK)
+            }
+            Err(err) => io_error_to_hresult(err),
+        }
+    }
+}
+
+impl ComInterfaceQuerier for SliceStream {}
+
 impl fmt::Debug for Stream {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         fmt::Debug::fmt(&self.0, f)
@@ -107,17 +591,123 @@ impl Default for MemoryStream {
     }
 }
 
-impl AsRef<[u8]> for MemoryStream {
-    fn as_ref(&self) -> &[u8] {
+impl MemoryStream {
+    pub fn from_slice(data: &[u8]) -> io::Result<Self> {
+        let mut stream = Self::default();
+        stream.write_all(data)?;
+        stream.seek(SeekFrom::Start(0))?;
+        Ok(stream)
+    }
+
+    /// Slices of the stream fail to write while data is borrowed
+    pub fn data(&self) -> MemoryStreamData<'_> {
+        BORROWED_DATA.lock().borrow_mut().push(self.addr());
+        MemoryStreamData(self)
+    }
+
+    fn addr(&self) -> usize {
+        (self.0).0.as_raw().as_ptr() as usize
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.data().to_vec()
+    }
+
+    pub fn truncate(&mut self, len: usize) -> io::Result<()> {
+        if (len as i64) < self.size() {
+            let pos = self.pos().min(len as i64);
+            self.set_size(len as i64)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            self.seek(SeekFrom::Start(pos as u64))?;
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.truncate(0)
+    }
+}
+
+pub struct MemoryStreamData<'a>(&'a MemoryStream);
+
+impl Deref for MemoryStreamData<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        let stream = &(self.0).0;
+        let size = stream.size() as usize;
+        if size == 0 {
+            return &[];
+        }
+        // the pointer is valid until stream is resized or written,
+        // which requires `&mut MemoryStream` and slices are blocked by `BORROWED_DATA`
         unsafe {
-            slice::from_raw_parts(
-                self.as_inner::<dyn IAIMPMemoryStream>().get_data(),
-                self.size() as usize,
-            )
+            slice::from_raw_parts(stream.as_inner::<dyn IAIMPMemoryStream>().get_data(), size)
         }
     }
 }
 
+impl Drop for MemoryStreamData<'_> {
+    fn drop(&mut self) {
+        let borrowed = BORROWED_DATA.lock();
+        let mut borrowed = borrowed.borrow_mut();
+        let addr = self.0.addr();
+        if let Some(idx) = borrowed.iter().position(|&borrowed| borrowed == addr) {
+            borrowed.swap_remove(idx);
+        }
+    }
+}
+
+impl AsRef<[u8]> for MemoryStreamData<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl fmt::Debug for MemoryStreamData<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl TryFrom<Vec<u8>> for MemoryStream {
+    type Error = io::Error;
+
+    fn try_from(data: Vec<u8>) -> io::Result<Self> {
+        Self::from_slice(&data)
+    }
+}
+
+impl TryFrom<&[u8]> for MemoryStream {
+    type Error = io::Error;
+
+    fn try_from(data: &[u8]) -> io::Result<Self> {
+        Self::from_slice(data)
+    }
+}
+
+impl From<MemoryStream> for Vec<u8> {
+    fn from(stream: MemoryStream) -> Self {
+        stream.into_vec()
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl TryFrom<bytes::Bytes> for MemoryStream {
+    type Error = io::Error;
+
+    fn try_from(data: bytes::Bytes) -> io::Result<Self> {
+        Self::from_slice(&data)
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl From<MemoryStream> for bytes::Bytes {
+    fn from(stream: MemoryStream) -> Self {
+        stream.into_vec().into()
+    }
+}
+
 impl AsRef<Stream> for MemoryStream {
     fn as_ref(&self) -> &Stream {
         unsafe { &*(Deref::deref(self) as *const Stream) }
@@ -0,0 +1,217 @@
+use super::{SendStream, Stream};
+use crate::threading::{TaskHandle, THREADS};
+use futures::channel::oneshot;
+use parking_lot::Mutex;
+use std::{
+    collections::VecDeque,
+    io,
+    io::{Read, Seek, SeekFrom},
+    sync::Arc,
+};
+
+const DEFAULT_BLOCK_SIZE: usize = 64 * 1024;
+const DEFAULT_CACHE_BLOCKS: usize = 16;
+const DEFAULT_READ_AHEAD: usize = 2;
+
+type SharedStream = Arc<Mutex<Option<SendStream>>>;
+
+fn released() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "stream was released")
+}
+
+fn fetch(inner: &SharedStream, index: u64, block_size: usize) -> io::Result<Vec<u8>> {
+    let mut inner = inner.lock();
+    let stream = &mut inner.as_mut().ok_or_else(released)?.0;
+    stream.seek(SeekFrom::Start(index * block_size as u64))?;
+
+    let mut block = vec![0; block_size];
+    let mut len = 0;
+    while len < block_size {
+        match stream.read(&mut block[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    block.truncate(len);
+    Ok(block)
+}
+
+struct Pending {
+    index: u64,
+    rx: oneshot::Receiver<io::Result<Vec<u8>>>,
+    handle: TaskHandle,
+}
+
+/// Reads underlying stream by blocks and caches them
+///
+/// Underlying stream must not be changed by anyone else while it's buffered
+pub struct BufferedStream {
+    inner: SharedStream,
+    block_size: usize,
+    cache_blocks: usize,
+    read_ahead: usize,
+    cache: VecDeque<(u64, Vec<u8>)>,
+    pending: Vec<Pending>,
+    pos: u64,
+}
+
+impl BufferedStream {
+    pub fn new<T: Into<Stream>>(stream: T) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Some(SendStream(stream.into())))),
+            block_size: DEFAULT_BLOCK_SIZE,
+            cache_blocks: DEFAULT_CACHE_BLOCKS,
+            read_ahead: DEFAULT_READ_AHEAD,
+            cache: VecDeque::new(),
+            pending: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    pub fn block_size(mut self, bytes: usize) -> Self {
+        self.block_size = bytes.max(1);
+        self.cache.clear();
+        self
+    }
+
+    pub fn cache_blocks(mut self, blocks: usize) -> Self {
+        self.cache_blocks = blocks.max(1);
+        self
+    }
+
+    /// Number of blocks read in background after the current one, `0` disables read-ahead
+    pub fn read_ahead(mut self, blocks: usize) -> Self {
+        self.read_ahead = blocks;
+        self
+    }
+
+    pub fn into_inner(mut self) -> Stream {
+        self.pending.clear();
+        let stream = self.inner.lock().take().unwrap();
+        stream.0
+    }
+
+    fn cache(&mut self, index: u64, block: Vec<u8>) {
+        self.cache.push_back((index, block));
+        while self.cache.len() > self.cache_blocks {
+            self.cache.pop_front();
+        }
+    }
+
+    fn take_pending(&mut self, index: u64) -> Option<io::Result<Vec<u8>>> {
+        let idx = self.pending.iter().position(|p| p.index == index)?;
+        let pending = self.pending.remove(idx);
+        pending.handle.wait();
+        let mut rx = pending.rx;
+        rx.try_recv().ok().flatten()
+    }
+
+    // finished tasks are moved to the cache, so dropping their handles doesn't block
+    fn harvest(&mut self) {
+        let mut idx = 0;
+        while idx < self.pending.len() {
+            match self.pending[idx].rx.try_recv() {
+                Ok(None) => idx += 1,
+                Ok(Some(res)) => {
+                    let pending = self.pending.remove(idx);
+                    if let Ok(block) = res {
+                        self.cache(pending.index, block);
+                    }
+                }
+                Err(_) => drop(self.pending.remove(idx)),
+            }
+        }
+    }
+
+    fn block(&mut self, index: u64) -> io::Result<&[u8]> {
+        match self.cache.iter().position(|(i, _)| *i == index) {
+            Some(idx) => {
+                let entry = self.cache.remove(idx).unwrap();
+                self.cache.push_back(entry);
+            }
+            None => {
+                let block = match self.take_pending(index) {
+                    Some(res) => res?,
+                    None => fetch(&self.inner, index, self.block_size)?,
+                };
+                self.cache(index, block);
+            }
+        }
+        Ok(&self.cache.back().unwrap().1)
+    }
+
+    fn spawn_read_ahead(&mut self, from: u64) {
+        self.harvest();
+        for index in from..from + self.read_ahead as u64 {
+            if self.pending.len() >= self.read_ahead {
+                break;
+            }
+            let known = self.cache.iter().any(|(i, _)| *i == index)
+                || self.pending.iter().any(|p| p.index == index);
+            if known {
+                continue;
+            }
+
+            let inner = self.inner.clone();
+            let block_size = self.block_size;
+            let (tx, rx) = oneshot::channel();
+            let handle = THREADS.get().spawn(async move {
+                let _ = tx.send(fetch(&inner, index, block_size));
+            });
+            self.pending.push(Pending { index, rx, handle });
+        }
+    }
+}
+
+impl Read for BufferedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let block_size = self.block_size;
+        let index = self.pos / block_size as u64;
+        let offset = (self.pos % block_size as u64) as usize;
+
+        let block = self.block(index)?;
+        let len = block.len().saturating_sub(offset).min(buf.len());
+        buf[..len].copy_from_slice(&block[offset..offset + len]);
+        let eof = block.len() < block_size;
+
+        self.pos += len as u64;
+        if !eof && self.read_ahead > 0 {
+            self.spawn_read_ahead(index + 1);
+        }
+        Ok(len)
+    }
+}
+
+impl Seek for BufferedStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            SeekFrom::Current(offset) => (self.pos, offset),
+            SeekFrom::End(offset) => {
+                let inner = self.inner.lock();
+                let size = inner.as_ref().ok_or_else(released)?.0.size();
+                (size.max(0) as u64, offset)
+            }
+        };
+        let pos = base as i64 + offset;
+        if pos < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            ));
+        }
+        self.pos = pos as u64;
+        Ok(self.pos)
+    }
+}
+
+impl From<BufferedStream> for Stream {
+    fn from(stream: BufferedStream) -> Self {
+        Stream::from_rust_reader(stream)
+    }
+}
@@ -0,0 +1,553 @@
+use super::{SendStream, Stream};
+use crate::{
+    threading::{TaskHandle, THREADS},
+    ProgressCallback,
+};
+use futures::channel::oneshot;
+use iaimp::{ComInterfaceQuerier, IAIMPHashCode};
+use parking_lot::Mutex;
+use std::{
+    convert::TryInto,
+    fmt, io,
+    io::{Read, Seek, SeekFrom},
+    os::raw::c_int,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum DigestAlgorithm {
+    Crc32,
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    fn hasher(self) -> Box<dyn Hasher> {
+        match self {
+            DigestAlgorithm::Crc32 => Box::new(Crc32(!0)),
+            DigestAlgorithm::Md5 => Box::new(Md5::default()),
+            DigestAlgorithm::Sha1 => Box::new(Sha1::default()),
+            DigestAlgorithm::Sha256 => Box::new(Sha256::default()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Digest {
+    algorithm: DigestAlgorithm,
+    bytes: Vec<u8>,
+}
+
+impl Digest {
+    pub fn of(algorithm: DigestAlgorithm, data: &[u8]) -> Self {
+        let mut hasher = algorithm.hasher();
+        hasher.update(data);
+        Self {
+            algorithm,
+            bytes: hasher.finish(),
+        }
+    }
+
+    pub fn algorithm(&self) -> DigestAlgorithm {
+        self.algorithm
+    }
+
+    /// Big-endian bytes as digest is usually written
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Value for `IAIMPHashCode::GetHashCode`
+    pub fn hash_code(&self) -> i32 {
+        i32::from_be_bytes(self.bytes[..4].try_into().unwrap())
+    }
+}
+
+/// Lowercase hex
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.bytes
+            .iter()
+            .try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+impl IAIMPHashCode for Digest {
+    unsafe fn get_hash_code(&self) -> c_int {
+        self.hash_code()
+    }
+
+    unsafe fn recalculate(&self) {}
+}
+
+impl ComInterfaceQuerier for Digest {}
+
+fn canceled() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Interrupted,
+        "digest calculation was canceled",
+    )
+}
+
+// `progress` returns `true` to cancel calculation
+fn calculate<F>(
+    stream: &mut Stream,
+    algorithm: DigestAlgorithm,
+    mut progress: F,
+) -> io::Result<Digest>
+where
+    F: FnMut(Option<f32>) -> bool,
+{
+    let pos = stream.pos().max(0) as u64;
+    stream.seek(SeekFrom::Start(0))?;
+    let size = stream.size().max(0) as u64;
+
+    let mut hasher = algorithm.hasher();
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut total = 0;
+    let res = loop {
+        let read = match stream.read(&mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => break Err(err),
+        };
+        hasher.update(&buf[..read]);
+        total += read as u64;
+        // called for every chunk, so calculation can be canceled even if size is unknown
+        let value = if size > 0 {
+            Some(total as f32 / size as f32)
+        } else {
+            None
+        };
+        if progress(value) {
+            break Err(canceled());
+        }
+    };
+
+    stream.seek(SeekFrom::Start(pos))?;
+    res.map(|()| Digest {
+        algorithm,
+        bytes: hasher.finish(),
+    })
+}
+
+impl Stream {
+    /// Digest of the whole stream, position is restored afterwards
+    pub fn digest(&mut self, algorithm: DigestAlgorithm) -> io::Result<Digest> {
+        calculate(self, algorithm, |_| false)
+    }
+
+    /// Fails with `Interrupted` if user canceled calculation
+    pub fn digest_with_progress(
+        &mut self,
+        algorithm: DigestAlgorithm,
+        progress: &ProgressCallback,
+    ) -> io::Result<Digest> {
+        calculate(
+            self,
+            algorithm,
+            |value| matches!(value, Some(value) if progress.progress(value)),
+        )
+    }
+
+    /// Calculate digest on `THREADS`
+    pub fn spawn_digest(
+        self,
+        algorithm: DigestAlgorithm,
+        progress: Option<ProgressCallback>,
+    ) -> DigestTask {
+        let canceled = Arc::new(AtomicBool::new(false));
+        let task_canceled = canceled.clone();
+        let progress = SendProgress(progress);
+        // kept outside of the task, so it is returned even if task never runs
+        let stream = Arc::new(Mutex::new(Some(SendStream(self))));
+        let task_stream = stream.clone();
+        let (tx, rx) = oneshot::channel();
+
+        let handle = THREADS.get().spawn(async move {
+            let progress = progress;
+            let mut stream = task_stream.lock();
+            let res = calculate(&mut stream.as_mut().unwrap().0, algorithm, |value| {
+                let user_canceled = match (&progress.0, value) {
+                    (Some(progress), Some(value)) => progress.progress(value),
+                    _ => false,
+                };
+                user_canceled || task_canceled.load(Ordering::Relaxed)
+            });
+            let _ = tx.send(res);
+        });
+
+        DigestTask {
+            stream,
+            rx,
+            handle,
+            canceled,
+        }
+    }
+}
+
+struct SendProgress(Option<ProgressCallback>);
+
+// AIMP progress callbacks can be called from any thread
+unsafe impl Send for SendProgress {}
+
+pub struct DigestTask {
+    stream: Arc<Mutex<Option<SendStream>>>,
+    rx: oneshot::Receiver<io::Result<Digest>>,
+    handle: TaskHandle,
+    canceled: Arc<AtomicBool>,
+}
+
+impl DigestTask {
+    /// Task stops after current chunk, `wait` returns `Interrupted` error then
+    pub fn cancel(&self) {
+        self.canceled.store(true, Ordering::Relaxed);
+    }
+
+    /// Result is `Interrupted` error if AIMP canceled task before it started
+    pub fn wait(self) -> (Stream, io::Result<Digest>) {
+        self.handle.wait();
+        let res = futures::executor::block_on(self.rx).unwrap_or_else(|_| Err(canceled()));
+        (self.stream.lock().take().unwrap().0, res)
+    }
+}
+
+trait Hasher {
+    fn update(&mut self, data: &[u8]);
+
+    fn finish(self: Box<Self>) -> Vec<u8>;
+}
+
+struct Crc32(u32);
+
+impl Hasher for Crc32 {
+    fn update(&mut self, data: &[u8]) {
+        self.0 = data.iter().fold(self.0, |crc, &byte| {
+            (0..8).fold(crc ^ byte as u32, |crc, _| {
+                (crc >> 1) ^ (0xEDB8_8320 & (!(crc & 1)).wrapping_add(1))
+            })
+        });
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        (!self.0).to_be_bytes().to_vec()
+    }
+}
+
+// common padding of MD5 and SHA family
+#[derive(Default)]
+struct Blocks {
+    buf: Vec<u8>,
+    len: u64,
+}
+
+impl Blocks {
+    fn update<F: FnMut(&[u8; 64])>(&mut self, mut data: &[u8], mut compress: F) {
+        self.len += data.len() as u64;
+        if !self.buf.is_empty() {
+            let take = (64 - self.buf.len()).min(data.len());
+            self.buf.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buf.len() < 64 {
+                return;
+            }
+            compress(self.buf[..].try_into().unwrap());
+            self.buf.clear();
+        }
+        let mut chunks = data.chunks_exact(64);
+        for block in &mut chunks {
+            compress(block.try_into().unwrap());
+        }
+        self.buf.extend_from_slice(chunks.remainder());
+    }
+
+    fn finish<F: FnMut(&[u8; 64])>(mut self, len_bytes: [u8; 8], mut compress: F) {
+        let mut tail = vec![0x80];
+        tail.resize((119 - self.buf.len()) % 64 + 1, 0);
+        tail.extend_from_slice(&len_bytes);
+        self.update(&tail, &mut compress);
+    }
+}
+
+struct Md5 {
+    state: [u32; 4],
+    blocks: Blocks,
+}
+
+impl Default for Md5 {
+    fn default() -> Self {
+        Self {
+            state: [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476],
+            blocks: Blocks::default(),
+        }
+    }
+}
+
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+fn md5_compress(state: &mut [u32; 4], block: &[u8; 64]) {
+    let m = |i: usize| u32::from_le_bytes(block[i * 4..i * 4 + 4].try_into().unwrap());
+    let [mut a, mut b, mut c, mut d] = *state;
+    for i in 0..64 {
+        let (f, g) = match i / 16 {
+            0 => ((b & c) | (!b & d), i),
+            1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+            2 => (b ^ c ^ d, (3 * i + 5) % 16),
+            _ => (c ^ (b | !d), (7 * i) % 16),
+        };
+        let k = ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32;
+        let f = f.wrapping_add(a).wrapping_add(k).wrapping_add(m(g));
+        a = d;
+        d = c;
+        c = b;
+        b = b.wrapping_add(f.rotate_left(MD5_SHIFTS[i / 16 * 4 + i % 4]));
+    }
+    for (s, v) in state.iter_mut().zip(&[a, b, c, d]) {
+        *s = s.wrapping_add(*v);
+    }
+}
+
+impl Hasher for Md5 {
+    fn update(&mut self, data: &[u8]) {
+        let state = &mut self.state;
+        self.blocks.update(data, |block| md5_compress(state, block));
+    }
+
+    fn finish(mut self: Box<Self>) -> Vec<u8> {
+        let state = &mut self.state;
+        let len_bytes = (self.blocks.len * 8).to_le_bytes();
+        std::mem::take(&mut self.blocks).finish(len_bytes, |block| md5_compress(state, block));
+        self.state
+            .iter()
+            .flat_map(|s| s.to_le_bytes().to_vec())
+            .collect()
+    }
+}
+
+struct Sha1 {
+    state: [u32; 5],
+    blocks: Blocks,
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Self {
+            state: [
+                0x6745_2301,
+                0xefcd_ab89,
+                0x98ba_dcfe,
+                0x1032_5476,
+                0xc3d2_e1f0,
+            ],
+            blocks: Blocks::default(),
+        }
+    }
+}
+
+fn sha1_compress(state: &mut [u32; 5], block: &[u8; 64]) {
+    let mut w = [0u32; 80];
+    for i in 0..16 {
+        w[i] = u32::from_be_bytes(block[i * 4..i * 4 + 4].try_into().unwrap());
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e] = *state;
+    for (i, w) in w.iter().enumerate() {
+        let (f, k) = match i / 20 {
+            0 => ((b & c) | (!b & d), 0x5a82_7999),
+            1 => (b ^ c ^ d, 0x6ed9_eba1),
+            2 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+            _ => (b ^ c ^ d, 0xca62_c1d6),
+        };
+        let temp = a
+            .rotate_left(5)
+            .wrapping_add(f)
+            .wrapping_add(e)
+            .wrapping_add(k)
+            .wrapping_add(*w);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = temp;
+    }
+    for (s, v) in state.iter_mut().zip(&[a, b, c, d, e]) {
+        *s = s.wrapping_add(*v);
+    }
+}
+
+impl Hasher for Sha1 {
+    fn update(&mut self, data: &[u8]) {
+        let state = &mut self.state;
+        self.blocks
+            .update(data, |block| sha1_compress(state, block));
+    }
+
+    fn finish(mut self: Box<Self>) -> Vec<u8> {
+        let state = &mut self.state;
+        let len_bytes = (self.blocks.len * 8).to_be_bytes();
+        std::mem::take(&mut self.blocks).finish(len_bytes, |block| sha1_compress(state, block));
+        self.state
+            .iter()
+            .flat_map(|s| s.to_be_bytes().to_vec())
+            .collect()
+    }
+}
+
+struct Sha256 {
+    state: [u32; 8],
+    blocks: Blocks,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self {
+            state: [
+                0x6a09_e667,
+                0xbb67_ae85,
+                0x3c6e_f372,
+                0xa54f_f53a,
+                0x510e_527f,
+                0x9b05_688c,
+                0x1f83_d9ab,
+                0x5be0_cd19,
+            ],
+            blocks: Blocks::default(),
+        }
+    }
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a_2f98,
+    0x7137_4491,
+    0xb5c0_fbcf,
+    0xe9b5_dba5,
+    0x3956_c25b,
+    0x59f1_11f1,
+    0x923f_82a4,
+    0xab1c_5ed5,
+    0xd807_aa98,
+    0x1283_5b01,
+    0x2431_85be,
+    0x550c_7dc3,
+    0x72be_5d74,
+    0x80de_b1fe,
+    0x9bdc_06a7,
+    0xc19b_f174,
+    0xe49b_69c1,
+    0xefbe_4786,
+    0x0fc1_9dc6,
+    0x240c_a1cc,
+    0x2de9_2c6f,
+    0x4a74_84aa,
+    0x5cb0_a9dc,
+    0x76f9_88da,
+    0x983e_5152,
+    0xa831_c66d,
+    0xb003_27c8,
+    0xbf59_7fc7,
+    0xc6e0_0bf3,
+    0xd5a7_9147,
+    0x06ca_6351,
+    0x1429_2967,
+    0x27b7_0a85,
+    0x2e1b_2138,
+    0x4d2c_6dfc,
+    0x5338_0d13,
+    0x650a_7354,
+    0x766a_0abb,
+    0x81c2_c92e,
+    0x9272_2c85,
+    0xa2bf_e8a1,
+    0xa81a_664b,
+    0xc24b_8b70,
+    0xc76c_51a3,
+    0xd192_e819,
+    0xd699_0624,
+    0xf40e_3585,
+    0x106a_a070,
+    0x19a4_c116,
+    0x1e37_6c08,
+    0x2748_774c,
+    0x34b0_bcb5,
+    0x391c_0cb3,
+    0x4ed8_aa4a,
+    0x5b9c_ca4f,
+    0x682e_6ff3,
+    0x748f_82ee,
+    0x78a5_636f,
+    0x84c8_7814,
+    0x8cc7_0208,
+    0x90be_fffa,
+    0xa450_6ceb,
+    0xbef9_a3f7,
+    0xc671_78f2,
+];
+
+fn sha256_compress(state: &mut [u32; 8], block: &[u8; 64]) {
+    let mut w = [0u32; 64];
+    for i in 0..16 {
+        w[i] = u32::from_be_bytes(block[i * 4..i * 4 + 4].try_into().unwrap());
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+    let mut v = *state;
+    for (k, w) in SHA256_K.iter().zip(&w) {
+        let [a, b, c, d, e, f, g, h] = v;
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let temp1 = h
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(*k)
+            .wrapping_add(*w);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let temp2 = s0.wrapping_add(maj);
+        v = [
+            temp1.wrapping_add(temp2),
+            a,
+            b,
+            c,
+            d.wrapping_add(temp1),
+            e,
+            f,
+            g,
+        ];
+    }
+    for (s, v) in state.iter_mut().zip(&v) {
+        *s = s.wrapping_add(*v);
+    }
+}
+
+impl Hasher for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        let state = &mut self.state;
+        self.blocks
+            .update(data, |block| sha256_compress(state, block));
+    }
+
+    fn finish(mut self: Box<Self>) -> Vec<u8> {
+        let state = &mut self.state;
+        let len_bytes = (self.blocks.len * 8).to_be_bytes();
+        std::mem::take(&mut self.blocks).finish(len_bytes, |block| sha256_compress(state, block));
+        self.state
+            .iter()
+            .flat_map(|s| s.to_be_bytes().to_vec())
+            .collect()
+    }
+}
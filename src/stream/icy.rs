@@ -0,0 +1,184 @@
+use super::Stream;
+use parking_lot::Mutex;
+use std::{
+    io,
+    io::{Read, Seek, SeekFrom},
+    sync::Arc,
+};
+
+const BLOCK_UNIT: usize = 16;
+
+/// Metadata block of Shoutcast/Icecast stream
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct IcyMetadata {
+    fields: Vec<(String, String)>,
+}
+
+impl IcyMetadata {
+    /// Parses `Key='value';` pairs, trailing NUL padding is ignored
+    pub fn parse(block: &[u8]) -> Self {
+        let end = block.iter().rposition(|b| *b != 0).map_or(0, |pos| pos + 1);
+        let text = String::from_utf8_lossy(&block[..end]);
+
+        let mut fields = Vec::new();
+        let mut rest = &text[..];
+        while let Some(eq) = rest.find("='") {
+            let key = rest[..eq].trim().to_string();
+            let value = &rest[eq + 2..];
+            // values may contain quotes, so only `';` ends them
+            let (value, next) = match value.find("';") {
+                Some(end) => (&value[..end], &value[end + 2..]),
+                None => (value.trim_end().trim_end_matches('\''), ""),
+            };
+            fields.push((key, value.to_string()));
+            rest = next;
+        }
+        Self { fields }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn stream_title(&self) -> Option<&str> {
+        self.get("StreamTitle").filter(|title| !title.is_empty())
+    }
+
+    pub fn stream_url(&self) -> Option<&str> {
+        self.get("StreamUrl").filter(|url| !url.is_empty())
+    }
+
+    /// `StreamTitle` split by ` - ` into artist and title
+    pub fn artist_title(&self) -> (Option<&str>, Option<&str>) {
+        match self.stream_title() {
+            Some(title) => match title.find(" - ") {
+                Some(idx) => (Some(title[..idx].trim()), Some(title[idx + 3..].trim())),
+                None => (None, Some(title)),
+            },
+            None => (None, None),
+        }
+    }
+}
+
+/// Receives metadata changes of `IcyStream` after the stream is moved elsewhere
+#[derive(Debug, Default, Clone)]
+pub struct IcyWatch(Arc<Mutex<Option<IcyMetadata>>>);
+
+impl IcyWatch {
+    /// Metadata changed since last call
+    pub fn take(&self) -> Option<IcyMetadata> {
+        self.0.lock().take()
+    }
+}
+
+/// Strips ICY metadata blocks from radio stream
+///
+/// `metaint` is the value of `icy-metaint` response header, `0` means stream has no metadata
+pub struct IcyStream<R = Stream> {
+    inner: R,
+    metaint: usize,
+    remaining: usize,
+    pos: u64,
+    metadata: Option<IcyMetadata>,
+    watch: IcyWatch,
+}
+
+impl<R: Read> IcyStream<R> {
+    pub fn new(inner: R, metaint: usize) -> Self {
+        Self {
+            inner,
+            metaint,
+            remaining: metaint,
+            pos: 0,
+            metadata: None,
+            watch: IcyWatch::default(),
+        }
+    }
+
+    pub fn watch(&self) -> IcyWatch {
+        self.watch.clone()
+    }
+
+    /// Last received metadata
+    pub fn metadata(&self) -> Option<&IcyMetadata> {
+        self.metadata.as_ref()
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn read_metadata(&mut self) -> io::Result<()> {
+        let mut len = [0];
+        match self.inner.read_exact(&mut len) {
+            Ok(()) => {}
+            // stream ended right before metadata
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err),
+        }
+
+        let mut block = vec![0; len[0] as usize * BLOCK_UNIT];
+        self.inner.read_exact(&mut block)?;
+        if block.is_empty() {
+            return Ok(());
+        }
+        let metadata = IcyMetadata::parse(&block);
+        if self.metadata.as_ref() != Some(&metadata) {
+            *self.watch.0.lock() = Some(metadata.clone());
+            self.metadata = Some(metadata);
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for IcyStream<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = if self.metaint == 0 {
+            buf.len()
+        } else {
+            if self.remaining == 0 {
+                self.read_metadata()?;
+                self.remaining = self.metaint;
+            }
+            buf.len().min(self.remaining)
+        };
+
+        let read = self.inner.read(&mut buf[..len])?;
+        if self.metaint > 0 {
+            self.remaining -= read;
+        }
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+/// Only reports position, radio stream can't be sought
+impl<R: Read> Seek for IcyStream<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match pos {
+            SeekFrom::Start(pos) if pos == self.pos => Ok(pos),
+            SeekFrom::Current(0) => Ok(self.pos),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "ICY stream is not seekable",
+            )),
+        }
+    }
+}
+
+impl<R: Read + Send + 'static> From<IcyStream<R>> for Stream {
+    fn from(stream: IcyStream<R>) -> Self {
+        Stream::from_rust_reader(stream)
+    }
+}